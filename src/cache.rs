@@ -1,34 +1,54 @@
 use std::collections::HashMap;
 
-use dreammaker::objtree::TypeVar;
 use sea_orm::{
-    ActiveModelTrait, ActiveValue::Set, ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter,
-    TryIntoModel,
+    ActiveEnum, ActiveModelTrait, ActiveValue::Set, ColumnTrait, DatabaseTransaction, EntityTrait,
+    QueryFilter, TryInsertResult, TryIntoModel, sea_query::OnConflict,
 };
+use sha2::{Digest, Sha256};
 
 use crate::{
     IngesterError,
-    models::{self, proc_decl, type_decl, var_decl},
+    extract::{ParsedProc, ParsedVar, VarFlags},
+    models::{
+        self, proc_decl, proc_param, proc_signature, source_file, type_decl, var_decl,
+        var_value::{self, VarValueKind},
+    },
 };
 
 use models::proc_decl::Entity as ProcDecl;
+use models::proc_signature::Entity as ProcSignature;
+use models::source_file::Entity as SourceFile;
 use models::type_decl::Entity as TypeDecl;
 use models::var_decl::Entity as VarDecl;
+use models::var_value::Entity as VarValue;
 
 pub(crate) struct Cache {
     pub types: HashMap<String, type_decl::Model>,
-    pub vars: HashMap<VarKey, var_decl::Model>,
+    pub vars: HashMap<String, var_decl::Model>,
+    pub values: HashMap<ValueKey, var_value::Model>,
     pub procs: HashMap<String, proc_decl::Model>,
+    pub files: HashMap<String, source_file::Model>,
+    pub signatures: HashMap<String, proc_signature::Model>,
 }
 
-type VarKey = (String, Option<String>, String);
+type ValueKey = (
+    i32,
+    Option<String>,
+    VarFlags,
+    VarValueKind,
+    String,
+    Option<String>,
+);
 
 impl Cache {
     pub(crate) fn new() -> Self {
         Cache {
             types: Default::default(),
             vars: Default::default(),
+            values: Default::default(),
             procs: Default::default(),
+            files: Default::default(),
+            signatures: Default::default(),
         }
     }
 
@@ -73,41 +93,23 @@ impl Cache {
     pub(crate) async fn get_var_decl(
         &mut self,
         var_path: &str,
-        var: &TypeVar,
         txn: &DatabaseTransaction,
     ) -> Result<&var_decl::Model, IngesterError> {
-        let var_key = self.get_var_key(var_path.into(), var);
-        if self.vars.contains_key(&var_key) {
+        if self.vars.contains_key(var_path) {
             return self
                 .vars
-                .get(&var_key)
+                .get(var_path)
                 .ok_or(IngesterError::Cache("cannot get var from cache".into()));
         }
-
-        let mut declared_type_id = -1;
-        if let Some(ref declared_type_path) = var_key.1 {
-            let declared_type = self.get_type(declared_type_path.as_str(), txn).await?;
-            declared_type_id = declared_type.id;
-        }
-
         let model = VarDecl::find()
             .filter(var_decl::Column::Path.eq(var_path))
-            .filter(var_decl::Column::DeclaredTypeId.eq(declared_type_id))
-            .filter(var_decl::Column::JsonConstVal.eq(var_key.2.clone()))
             .one(txn)
             .await?;
-
         let var_decl = if let Some(var_decl) = model {
             var_decl
         } else {
             let x = var_decl::ActiveModel {
                 path: Set(var_path.into()),
-                declared_type_id: Set(if declared_type_id >= 0 {
-                    Some(declared_type_id)
-                } else {
-                    None
-                }),
-                json_const_val: Set(var_key.2.clone()),
                 ..Default::default()
             }
             .save(txn)
@@ -115,47 +117,104 @@ impl Cache {
 
             x.try_into_model()?
         };
-        self.vars.insert(var_key.clone(), var_decl.to_owned());
+        self.vars.insert(var_path.into(), var_decl.to_owned());
         self.vars
-            .get(&var_key)
+            .get(var_path)
             .ok_or(IngesterError::Cache("cannot get var from cache".into()))
     }
 
-    fn get_var_key(&self, var_path: String, var: &TypeVar) -> VarKey {
-        let mut declared_type: Option<String> = None;
-        if let Some(var_decl) = &var.declaration {
-            declared_type = Some(var_decl.var_type.type_path.join("/"));
+    pub(crate) async fn get_var_value(
+        &mut self,
+        var_decl_id: i32,
+        var: &ParsedVar,
+        txn: &DatabaseTransaction,
+    ) -> Result<&var_value::Model, IngesterError> {
+        let value_key: ValueKey = (
+            var_decl_id,
+            var.declared_type.clone(),
+            var.flags,
+            var.value_kind,
+            var.json_const_val.clone(),
+            var.expression.clone(),
+        );
+        if self.values.contains_key(&value_key) {
+            return self.values.get(&value_key).ok_or(IngesterError::Cache(
+                "cannot get var value from cache".into(),
+            ));
         }
 
-        let mut json_const_val = "".to_string();
-        if let Some(const_val) = &var.value.constant {
-            match const_val {
-                // TODO(wso): support serializing more types here
-                dreammaker::constants::Constant::Null(_) => {
-                    json_const_val = "null".to_string();
-                }
-                dreammaker::constants::Constant::Prefab(pop) => {
-                    json_const_val = format!("\"{}\"", pop);
-                }
-                dreammaker::constants::Constant::String(ident2) => {
-                    json_const_val = format!("\"{}\"", ident2);
-                }
-                dreammaker::constants::Constant::Resource(ident2) => {
-                    json_const_val = format!("\"{}\"", ident2);
-                }
-                dreammaker::constants::Constant::Float(f) => {
-                    json_const_val = format!("{}", f);
-                }
-                _ => {}
-            }
+        let mut declared_type_id = None;
+        if let Some(ref declared_type_path) = var.declared_type {
+            let declared_type = self.get_type(declared_type_path.as_str(), txn).await?;
+            declared_type_id = Some(declared_type.id);
         }
 
-        (var_path.clone(), declared_type, json_const_val)
+        let var_decl = var_decl_id.to_string();
+        let flags = [
+            var.flags.is_const,
+            var.flags.is_static,
+            var.flags.is_tmp,
+            var.flags.is_final,
+        ]
+        .map(|flag| if flag { "1" } else { "0" })
+        .concat();
+        let value_kind = var.value_kind.to_value();
+        let value_hash = content_hash([
+            Some(var_decl.as_str()),
+            var.declared_type.as_deref(),
+            Some(flags.as_str()),
+            Some(value_kind.as_str()),
+            Some(var.json_const_val.as_str()),
+            var.expression.as_deref(),
+        ]);
+
+        let model = VarValue::find()
+            .filter(var_value::Column::ValueHash.eq(&value_hash))
+            .one(txn)
+            .await?;
+        let var_value = if let Some(var_value) = model {
+            var_value
+        } else {
+            VarValue::insert(var_value::ActiveModel {
+                var_decl_id: Set(var_decl_id),
+                declared_type_id: Set(declared_type_id),
+                is_const: Set(var.flags.is_const),
+                is_static: Set(var.flags.is_static),
+                is_tmp: Set(var.flags.is_tmp),
+                is_final: Set(var.flags.is_final),
+                value_kind: Set(var.value_kind),
+                json_const_val: Set(var.json_const_val.clone()),
+                expression: Set(var.expression.clone()),
+                value_hash: Set(value_hash.clone()),
+                ..Default::default()
+            })
+            .on_conflict(
+                OnConflict::column(var_value::Column::ValueHash)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .do_nothing()
+            .exec(txn)
+            .await?;
+            VarValue::find()
+                .filter(var_value::Column::ValueHash.eq(&value_hash))
+                .one(txn)
+                .await?
+                .ok_or(IngesterError::Cache(
+                    "cannot find inserted var value".into(),
+                ))?
+        };
+        self.values.insert(value_key.clone(), var_value.to_owned());
+        self.values.get(&value_key).ok_or(IngesterError::Cache(
+            "cannot get var value from cache".into(),
+        ))
     }
 
     pub(crate) async fn get_proc(
         &mut self,
         path: &str,
+        type_decl_id: i32,
+        name: &str,
         txn: &DatabaseTransaction,
     ) -> Result<&proc_decl::Model, IngesterError> {
         if self.procs.contains_key(path) {
@@ -173,6 +232,8 @@ impl Cache {
         } else {
             let x = proc_decl::ActiveModel {
                 path: Set(path.into()),
+                type_decl_id: Set(type_decl_id),
+                name: Set(name.into()),
                 ..Default::default()
             }
             .save(txn)
@@ -185,4 +246,130 @@ impl Cache {
             .get(path)
             .ok_or(IngesterError::Cache("cannot get type from cache".into()))
     }
+
+    pub(crate) async fn get_source_file(
+        &mut self,
+        path: &str,
+        txn: &DatabaseTransaction,
+    ) -> Result<&source_file::Model, IngesterError> {
+        if self.files.contains_key(path) {
+            return self
+                .files
+                .get(path)
+                .ok_or(IngesterError::Cache("cannot get file from cache".into()));
+        }
+        let model = SourceFile::find()
+            .filter(source_file::Column::Path.eq(path))
+            .one(txn)
+            .await?;
+        let source_file = if let Some(source_file) = model {
+            source_file
+        } else {
+            let x = source_file::ActiveModel {
+                path: Set(path.into()),
+                ..Default::default()
+            }
+            .save(txn)
+            .await?;
+
+            x.try_into_model()?
+        };
+        self.files.insert(path.into(), source_file.to_owned());
+        self.files
+            .get(path)
+            .ok_or(IngesterError::Cache("cannot get file from cache".into()))
+    }
+
+    pub(crate) async fn get_proc_signature(
+        &mut self,
+        proc: &ParsedProc,
+        txn: &DatabaseTransaction,
+    ) -> Result<&proc_signature::Model, IngesterError> {
+        let signature = proc.signature();
+        if self.signatures.contains_key(&signature) {
+            return self.signatures.get(&signature).ok_or(IngesterError::Cache(
+                "cannot get signature from cache".into(),
+            ));
+        }
+        let signature_hash = content_hash([Some(signature.as_str())]);
+        let model = ProcSignature::find()
+            .filter(proc_signature::Column::SignatureHash.eq(&signature_hash))
+            .one(txn)
+            .await?;
+        let proc_signature = if let Some(proc_signature) = model {
+            proc_signature
+        } else {
+            // Another run may have inserted the same signature since the
+            // lookup; the unique hash makes that a no-op.
+            let inserted = ProcSignature::insert(proc_signature::ActiveModel {
+                signature: Set(signature.clone()),
+                signature_hash: Set(signature_hash.clone()),
+                ..Default::default()
+            })
+            .on_conflict(
+                OnConflict::column(proc_signature::Column::SignatureHash)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .do_nothing()
+            .exec(txn)
+            .await?;
+            let proc_signature = ProcSignature::find()
+                .filter(proc_signature::Column::SignatureHash.eq(&signature_hash))
+                .one(txn)
+                .await?
+                .ok_or(IngesterError::Cache(
+                    "cannot find inserted signature".into(),
+                ))?;
+
+            if let TryInsertResult::Inserted(_) = inserted {
+                let params: Vec<_> = proc
+                    .params
+                    .iter()
+                    .enumerate()
+                    .map(|(position, param)| proc_param::ActiveModel {
+                        proc_signature_id: Set(proc_signature.id),
+                        position: Set(position as i32),
+                        name: Set(param.name.clone()),
+                        declared_type: Set(param.declared_type.clone()),
+                        default: Set(param.default.clone()),
+                        input_type: Set(param.input_type.clone()),
+                        in_list: Set(param.in_list.clone()),
+                    })
+                    .collect();
+                if !params.is_empty() {
+                    proc_param::Entity::insert_many(params).exec(txn).await?;
+                }
+            }
+
+            proc_signature
+        };
+        self.signatures.insert(signature.clone(), proc_signature);
+        self.signatures.get(&signature).ok_or(IngesterError::Cache(
+            "cannot get signature from cache".into(),
+        ))
+    }
+}
+
+/// Hex SHA-256 of `parts`, for looking up and uniquely indexing rows whose
+/// identity is too long to index directly. Each part is length-prefixed, with
+/// `None` distinct from an empty string, so different parts never hash alike
+/// by running into each other.
+pub(crate) fn content_hash<'a>(parts: impl IntoIterator<Item = Option<&'a str>>) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        match part {
+            Some(part) => {
+                hasher.update([1]);
+                hasher.update((part.len() as u64).to_le_bytes());
+                hasher.update(part.as_bytes());
+            }
+            None => hasher.update([0]),
+        }
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
 }
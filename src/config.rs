@@ -5,13 +5,60 @@ pub(crate) struct IntegrationsConfig {
     pub db_connection_string: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub(crate) struct EnvironmentConfig {
+    /// Directory the DM sources of each commit are extracted into for
+    /// parsing. Defaults to a directory under the system temp dir.
+    pub scratch_dir: Option<String>,
+    /// Directory parse results are kept in, keyed by git tree hash, so
+    /// commits that were parsed before aren't parsed again. Disabled if unset.
+    pub parse_cache_dir: Option<String>,
+    /// The repository to ingest, from before `[[codebases]]` existed. See
+    /// `Config::upgrade_legacy`.
+    pub repo_root: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct CodebaseConfig {
+    /// Name the codebase is recorded under in the database.
+    pub name: String,
     pub repo_root: String,
+    /// Full ref path to ingest, e.g. `refs/remotes/upstream/master`.
+    pub refpath: String,
+    /// Name of the `.dme` environment to parse, e.g. `tgstation.dme`. If
+    /// unset, the single `.dme` at the root of the repository is used.
+    pub dme: Option<String>,
+    /// Var names, e.g. `force`, whose effective value is recorded for every
+    /// type in each snapshot, including types that only inherit it.
+    #[serde(default)]
+    pub inherited_vars: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct Config {
     pub integrations: IntegrationsConfig,
+    #[serde(default)]
     pub environment: EnvironmentConfig,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub codebases: Vec<CodebaseConfig>,
+}
+
+impl Config {
+    /// Turns settings from before `[[codebases]]` existed, which only set
+    /// `[environment] repo_root`, into a single codebase named `default` that
+    /// parses `paradise.dme` like those versions did. Its `refpath` is `HEAD`
+    /// unless overridden with `--refpath`.
+    pub(crate) fn upgrade_legacy(&mut self) {
+        if self.codebases.is_empty()
+            && let Some(repo_root) = self.environment.repo_root.take()
+        {
+            self.codebases.push(CodebaseConfig {
+                name: "default".into(),
+                repo_root,
+                refpath: "HEAD".into(),
+                dme: Some("paradise.dme".into()),
+                inherited_vars: vec![],
+            });
+        }
+    }
+}
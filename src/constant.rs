@@ -0,0 +1,165 @@
+use dreammaker::constants::{Constant, Pop};
+use serde_json::{Map, Value, json};
+
+/// Encodes a DM constant as JSON.
+///
+/// Nulls, numbers and strings map onto their JSON counterparts. Everything
+/// else becomes an object tagged by kind:
+///
+/// - resources: `{"resource": "icons/obj/foo.dmi"}`
+/// - prefabs: `{"prefab": "/obj/item", "vars": {"name": "foo"}}`, with `vars`
+///   left out when there are none
+/// - lists: `{"list": [[key], [key, value]]}`, one array per entry holding the
+///   key and, for associative entries, the value
+/// - `new` calls: `{"new": <prefab or null>, "args": [...]}`, with `args` left
+///   out when there is no argument list
+/// - other constant calls such as `newlist()` or `icon()`:
+///   `{"call": "newlist", "args": [...]}`
+/// - non-finite numbers: `{"float": "inf"}`, `{"float": "-inf"}` or
+///   `{"float": "nan"}`
+pub(crate) fn to_json(constant: &Constant) -> String {
+    encode(constant).to_string()
+}
+
+fn encode(constant: &Constant) -> Value {
+    match constant {
+        Constant::Null(_) => Value::Null,
+        Constant::Float(f) => encode_float(*f),
+        Constant::String(s) => Value::String(s.to_string()),
+        Constant::Resource(s) => json!({ "resource": s.to_string() }),
+        Constant::Prefab(pop) => encode_pop(pop),
+        Constant::List(entries) => json!({ "list": encode_entries(entries) }),
+        Constant::New { type_, args } => {
+            let mut object = Map::new();
+            object.insert(
+                "new".into(),
+                type_.as_ref().map_or(Value::Null, |pop| encode_pop(pop)),
+            );
+            if let Some(args) = args {
+                object.insert("args".into(), encode_entries(args));
+            }
+            Value::Object(object)
+        }
+        Constant::Call(func, args) => json!({
+            "call": func.to_string(),
+            "args": encode_entries(args),
+        }),
+    }
+}
+
+fn encode_float(f: f32) -> Value {
+    if f.is_nan() {
+        json!({ "float": "nan" })
+    } else if f.is_infinite() {
+        json!({ "float": if f > 0.0 { "inf" } else { "-inf" } })
+    } else {
+        // Go through the f32's shortest representation so that e.g. 0.1 is
+        // stored as 0.1 rather than 0.10000000149011612.
+        f.to_string()
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or(Value::Null, Value::Number)
+    }
+}
+
+fn encode_pop(pop: &Pop) -> Value {
+    let mut object = Map::new();
+    object.insert(
+        "prefab".into(),
+        Value::String(format!("/{}", pop.path.join("/"))),
+    );
+    if !pop.vars.is_empty() {
+        let vars = pop
+            .vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), encode(value)))
+            .collect();
+        object.insert("vars".into(), Value::Object(vars));
+    }
+    Value::Object(object)
+}
+
+fn encode_entries(entries: &[(Constant, Option<Constant>)]) -> Value {
+    Value::Array(
+        entries
+            .iter()
+            .map(|(key, value)| {
+                let mut entry = vec![encode(key)];
+                if let Some(value) = value {
+                    entry.push(encode(value));
+                }
+                Value::Array(entry)
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use dreammaker::constants::Constant;
+    use serde_json::{Value, json};
+
+    use super::to_json;
+    use crate::dme::parse_for_test;
+
+    /// The encoding of the value of var `name` on `/datum/test` in `code`,
+    /// parsed back into JSON.
+    fn var_json(code: &str, name: &str) -> Value {
+        let tree = parse_for_test(code);
+        let type_ = tree.find("/datum/test").unwrap();
+        let constant = type_.vars[name].value.constant.as_ref().unwrap();
+        serde_json::from_str(&to_json(constant)).unwrap()
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let code = r#"
+/datum/test
+	var/quoted = "say \"hi\" \\ bye\n"
+"#;
+        assert_eq!(var_json(code, "quoted"), json!("say \"hi\" \\ bye\n"));
+    }
+
+    #[test]
+    fn encodes_nested_associative_lists() {
+        let code = r#"
+/datum/test
+	var/list/nested = list("a" = list("b" = 1), "c", null)
+"#;
+        assert_eq!(
+            var_json(code, "nested"),
+            json!({ "list": [["a", { "list": [["b", 1.0]] }], ["c"], [null]] })
+        );
+    }
+
+    #[test]
+    fn encodes_prefab_vars() {
+        let code = r#"
+/obj/item
+/datum/test
+	var/bare = /obj/item
+	var/prefab = /obj/item{name = "foo"; force = 5}
+"#;
+        assert_eq!(var_json(code, "bare"), json!({ "prefab": "/obj/item" }));
+        assert_eq!(
+            var_json(code, "prefab"),
+            json!({ "prefab": "/obj/item", "vars": { "name": "foo", "force": 5.0 } })
+        );
+    }
+
+    #[test]
+    fn encodes_non_finite_floats() {
+        assert_eq!(to_json(&Constant::Float(0.1)), "0.1");
+        assert_eq!(
+            to_json(&Constant::Float(f32::INFINITY)),
+            r#"{"float":"inf"}"#
+        );
+        assert_eq!(
+            to_json(&Constant::Float(f32::NEG_INFINITY)),
+            r#"{"float":"-inf"}"#
+        );
+        assert_eq!(to_json(&Constant::Float(f32::NAN)), r#"{"float":"nan"}"#);
+        assert_eq!(to_json(&Constant::Null(None)), "null");
+    }
+}
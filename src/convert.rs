@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+
+use git2::Oid;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect,
+    sea_query::Expr,
+};
+use slog::{Logger, info};
+
+use crate::IngesterError;
+
+/// The tables of a database written before codebases existed, children
+/// first so they can be dropped in order.
+const LEGACY_TABLES: &[&str] = &[
+    "var_decl_snapshot",
+    "proc_decl_snapshot",
+    "type_decl_snapshot",
+    "snapshot",
+    "git_commit_log_numstat_entry",
+    "git_log_entry",
+    "var_decl",
+    "proc_decl",
+    "type_decl",
+];
+
+/// The commits a legacy database had snapshots of, kept between dropping its
+/// tables and re-ingesting the commits.
+mod legacy_commit {
+    use sea_orm::DeriveEntityModel;
+    use sea_orm::entity::prelude::*;
+
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "legacy_commit")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub commit_hash: String,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+/// The columns of every table the server knows of.
+mod information_schema_column {
+    use sea_orm::DeriveEntityModel;
+    use sea_orm::entity::prelude::*;
+
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(schema_name = "information_schema", table_name = "COLUMNS")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub table_schema: String,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub table_name: String,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub column_name: String,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+/// Replaces the tables of a database written before codebases existed with
+/// the list of commits it had snapshots of, and returns those commits. Their
+/// decls are re-derived by ingesting them again, since the legacy tables
+/// lack most of what is stored now. Picks up where an interrupted conversion
+/// left off.
+pub(crate) async fn take_legacy_commits(
+    db: &DatabaseConnection,
+    logger: &Logger,
+) -> Result<HashSet<Oid>, IngesterError> {
+    use information_schema_column::{Column, Entity as InformationSchemaColumn};
+
+    let columns: Vec<(String, String)> = InformationSchemaColumn::find()
+        .select_only()
+        .column(Column::TableName)
+        .column(Column::ColumnName)
+        .filter(Expr::col(Column::TableSchema).eq(Expr::cust("DATABASE()")))
+        .filter(Column::TableName.is_in(["snapshot", "legacy_commit"]))
+        .into_tuple()
+        .all(db)
+        .await?;
+    let has_table = |table: &str| columns.iter().any(|(name, _)| name == table);
+    let legacy_snapshot = has_table("snapshot")
+        && !columns
+            .iter()
+            .any(|(table, column)| table == "snapshot" && column == "codebase_id");
+
+    if legacy_snapshot {
+        info!(logger, "dropping legacy tables");
+        if !has_table("legacy_commit") {
+            db.get_schema_builder()
+                .register(legacy_commit::Entity)
+                .apply(db)
+                .await?;
+        }
+        db.execute_unprepared(
+            "INSERT IGNORE INTO legacy_commit (commit_hash) \
+             SELECT g.commit_hash FROM snapshot s \
+             JOIN git_log_entry g ON g.id = s.git_log_entry_id",
+        )
+        .await?;
+        db.execute_unprepared(&format!("DROP TABLE {}", LEGACY_TABLES.join(", ")))
+            .await?;
+    } else if !has_table("legacy_commit") {
+        return Err(IngesterError::Args(
+            "--convert found no database written before codebases existed".into(),
+        ));
+    }
+
+    Ok(legacy_commit::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .filter_map(|commit| Oid::from_str(&commit.commit_hash).ok())
+        .collect())
+}
+
+/// Drops the commit list left by [`take_legacy_commits`] once they have all
+/// been ingested.
+pub(crate) async fn finish(db: &DatabaseConnection) -> Result<(), IngesterError> {
+    db.execute_unprepared("DROP TABLE legacy_commit").await?;
+    Ok(())
+}
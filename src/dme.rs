@@ -1,15 +1,40 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use dreammaker::objtree::ObjectTree;
 
 use crate::IngesterError;
 
-pub(crate) fn get_object_tree(path: PathBuf) -> Result<ObjectTree, IngesterError> {
+/// Finds the name of the single `.dme` environment at the root of `tree`.
+pub(crate) fn detect_dme(tree: &git2::Tree) -> Result<String, IngesterError> {
+    let mut found = tree.iter().filter_map(|entry| {
+        let name = entry.name()?;
+        let is_dme = Path::new(name)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("dme"));
+        (is_dme && entry.kind() == Some(git2::ObjectType::Blob)).then(|| name.to_owned())
+    });
+
+    match (found.next(), found.next()) {
+        (Some(name), None) => Ok(name),
+        (None, _) => Err(IngesterError::Parser(
+            "no .dme file found at the repository root, set `dme` in settings".into(),
+        )),
+        (Some(_), Some(_)) => Err(IngesterError::Parser(
+            "multiple .dme files found at the repository root, set `dme` in settings".into(),
+        )),
+    }
+}
+
+/// Parses the environment at `path`. Diagnostics raised along the way are left
+/// in `ctx` for the caller to collect.
+pub(crate) fn get_object_tree(
+    ctx: &dreammaker::Context,
+    path: PathBuf,
+) -> Result<ObjectTree, IngesterError> {
     if !path.is_file() {
         return Err(IngesterError::Parser(format!("file not found: {:?}", path)));
     }
-    let ctx = dreammaker::Context::default();
-    let pp = match dreammaker::preprocessor::Preprocessor::new(&ctx, path.clone()) {
+    let pp = match dreammaker::preprocessor::Preprocessor::new(ctx, path.clone()) {
         Ok(pp) => pp,
         Err(e) => {
             return Err(IngesterError::Parser(format!(
@@ -18,8 +43,8 @@ pub(crate) fn get_object_tree(path: PathBuf) -> Result<ObjectTree, IngesterError
             )));
         }
     };
-    let indents = dreammaker::indents::IndentProcessor::new(&ctx, pp);
-    let mut parser = dreammaker::parser::Parser::new(&ctx, indents);
+    let indents = dreammaker::indents::IndentProcessor::new(ctx, pp);
+    let mut parser = dreammaker::parser::Parser::new(ctx, indents);
 
     parser.enable_procs();
 
@@ -32,4 +57,13 @@ pub(crate) fn get_object_tree(path: PathBuf) -> Result<ObjectTree, IngesterError
     } else {
         Ok(tree)
     }
-}
\ No newline at end of file
+}
+
+/// Parses `code` as the only file of an environment, for tests.
+#[cfg(test)]
+pub(crate) fn parse_for_test(code: &str) -> ObjectTree {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.dme");
+    std::fs::write(&path, code).unwrap();
+    get_object_tree(&dreammaker::Context::default(), path).unwrap()
+}
@@ -0,0 +1,774 @@
+use std::path::Path;
+
+use dreammaker::{
+    Context, FileId, Location, Severity,
+    ast::{
+        AssignOp, BinaryOp, Expression, Follow, ListAccessKind, Parameter, ProcDeclKind,
+        PropertyAccessKind, Spanned, Statement, Term,
+    },
+    objtree::{ObjectTree, ProcValue, TypeProc, TypeRef, TypeVar},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constant,
+    models::{
+        diagnostic::DiagnosticSeverity,
+        proc_decl_validity::{ParentCall, ProcKind},
+        var_value::VarValueKind,
+    },
+};
+
+/// The decls of a parsed object tree, detached from dreammaker's types so they
+/// can be handed from a parser thread to the database writer.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct ParsedTree {
+    pub types: Vec<ParsedType>,
+}
+
+/// Where in the source a decl lives. Builtins have no location.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ParsedLocation {
+    /// Path of the file relative to the repository root, with `/` separators.
+    pub file: String,
+    pub line: u32,
+    pub column: u16,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ParsedType {
+    pub path: String,
+    /// The effective parent type, taking `parent_type` into account. `None`
+    /// only for the root.
+    pub parent: Option<String>,
+    /// Whether the parent was set with `parent_type` rather than implied by
+    /// the path.
+    pub explicit_parent: bool,
+    pub location: Option<ParsedLocation>,
+    pub procs: Vec<ParsedProc>,
+    pub vars: Vec<ParsedVar>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ParsedProc {
+    pub name: String,
+    /// The last definition of the proc on the type, which is the one that
+    /// takes effect.
+    pub location: Option<ParsedLocation>,
+    /// Parameters of that same definition.
+    pub params: Vec<ParsedParam>,
+    /// Whether this is a `proc` or a `verb`, as declared here or on the
+    /// nearest ancestor that declares it.
+    pub kind: ProcKind,
+    /// Whether the type overrides a proc declared on an ancestor rather than
+    /// declaring it itself.
+    pub is_override: bool,
+    /// How many times the proc is defined on this type.
+    pub definition_count: usize,
+    pub is_static: bool,
+    pub is_final: bool,
+    /// `set SpacemanDMM_*` settings in the effective definition, as
+    /// `name = value`.
+    pub settings: Vec<String>,
+    /// Whether the effective definition calls `..()`.
+    pub parent_call: ParentCall,
+    /// Whether the nearest ancestor definition that sets
+    /// `SpacemanDMM_should_call_parent` turns it on.
+    pub should_call_parent: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ParsedParam {
+    pub name: String,
+    pub declared_type: Option<String>,
+    pub default: Option<String>,
+    /// The `as` input modes, e.g. `mob|obj`.
+    pub input_type: Option<String>,
+    pub in_list: Option<String>,
+}
+
+impl ParsedProc {
+    /// The parameter list as it would be written in DM, e.g.
+    /// `mob/user, amount = 1, target as mob in view()`. Used as the identity of
+    /// the signature.
+    pub(crate) fn signature(&self) -> String {
+        self.params
+            .iter()
+            .map(|param| {
+                let mut out = String::new();
+                if let Some(declared_type) = &param.declared_type {
+                    out.push_str(declared_type);
+                    out.push('/');
+                }
+                out.push_str(&param.name);
+                if let Some(default) = &param.default {
+                    out.push_str(" = ");
+                    out.push_str(default);
+                }
+                if let Some(input_type) = &param.input_type {
+                    out.push_str(" as ");
+                    out.push_str(input_type);
+                }
+                if let Some(in_list) = &param.in_list {
+                    out.push_str(" in ");
+                    out.push_str(in_list);
+                }
+                out
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ParsedVar {
+    pub name: String,
+    pub location: Option<ParsedLocation>,
+    pub declared_type: Option<String>,
+    pub flags: VarFlags,
+    pub value_kind: VarValueKind,
+    /// JSON encoding of the value, when it could be constant-evaluated.
+    pub json_const_val: String,
+    /// The initializer as written, when it couldn't be constant-evaluated.
+    pub expression: Option<String>,
+}
+
+/// Declaration flags of a var, as declared here or on the nearest ancestor
+/// that declares it. `global` vars are `static`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct VarFlags {
+    pub is_const: bool,
+    pub is_static: bool,
+    pub is_tmp: bool,
+    pub is_final: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ParsedDiagnostic {
+    pub severity: DiagnosticSeverity,
+    /// Path of the offending file relative to the repository root, with `/`
+    /// separators.
+    pub file: String,
+    pub line: u32,
+    pub column: u16,
+    pub message: String,
+    pub code: Option<String>,
+}
+
+/// Extracts the decls of `tree`, which was parsed in `ctx` from the
+/// environment checked out under `root`.
+pub(crate) fn extract(tree: &ObjectTree, ctx: &Context, root: &Path) -> ParsedTree {
+    let types = tree
+        .iter_types()
+        .map(|type_| ParsedType {
+            path: type_.path.clone(),
+            parent: type_.parent_type().map(|parent| parent.path.clone()),
+            explicit_parent: explicit_parent(type_),
+            location: extract_location(ctx, root, type_.location),
+            procs: type_
+                .procs
+                .iter()
+                .map(|(name, proc)| extract_proc(ctx, root, type_, name, proc))
+                .collect(),
+            vars: type_
+                .vars
+                .iter()
+                .map(|(name, var)| extract_var(ctx, root, type_, name, var))
+                .collect(),
+        })
+        .collect();
+
+    ParsedTree { types }
+}
+
+/// Whether the type sets `parent_type` itself. Builtins declare it on `/datum`
+/// and friends, which doesn't make their parent explicit.
+fn explicit_parent(type_: TypeRef) -> bool {
+    type_
+        .vars
+        .get("parent_type")
+        .is_some_and(|var| !var.value.location.is_builtins())
+}
+
+fn extract_location(ctx: &Context, root: &Path, location: Location) -> Option<ParsedLocation> {
+    if location.is_builtins() {
+        return None;
+    }
+
+    Some(ParsedLocation {
+        file: relative_path(ctx, root, location.file),
+        line: location.line,
+        column: location.column,
+    })
+}
+
+fn relative_path(ctx: &Context, root: &Path, file: FileId) -> String {
+    let path = ctx.file_path(file);
+    path.strip_prefix(root)
+        .unwrap_or(&path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn extract_proc(
+    ctx: &Context,
+    root: &Path,
+    type_: TypeRef,
+    name: &str,
+    proc: &TypeProc,
+) -> ParsedProc {
+    let value = proc.value.last();
+    let declaration = proc
+        .declaration
+        .as_ref()
+        .or_else(|| type_.get_proc_declaration(name));
+
+    ParsedProc {
+        name: name.to_owned(),
+        location: value.and_then(|value| extract_location(ctx, root, value.location)),
+        params: value
+            .map(|value| value.parameters.iter().map(extract_param).collect())
+            .unwrap_or_default(),
+        kind: match declaration.map(|decl| decl.kind) {
+            Some(ProcDeclKind::Verb) => ProcKind::Verb,
+            _ => ProcKind::Proc,
+        },
+        is_override: proc.declaration.is_none(),
+        definition_count: proc.value.len(),
+        is_static: declaration.is_some_and(|decl| decl.flags.is_static()),
+        is_final: declaration.is_some_and(|decl| decl.flags.is_final()),
+        settings: value.map(proc_settings).unwrap_or_default(),
+        parent_call: value
+            .and_then(|value| value.code.as_deref())
+            .map(parent_call)
+            .unwrap_or(ParentCall::None),
+        should_call_parent: should_call_parent(type_, name),
+    }
+}
+
+/// The `set` statements in a proc definition, as `(name, value)`.
+fn settings(value: &ProcValue) -> impl Iterator<Item = (&str, &Expression)> {
+    value
+        .code
+        .iter()
+        .flat_map(|code| code.iter())
+        .filter_map(|statement| match &statement.elem {
+            Statement::Setting { name, value, .. } => Some((name.as_str(), value)),
+            _ => None,
+        })
+}
+
+fn proc_settings(value: &ProcValue) -> Vec<String> {
+    settings(value)
+        .filter(|(name, _)| name.starts_with("SpacemanDMM_"))
+        .map(|(name, value)| format!("{} = {}", name, value))
+        .collect()
+}
+
+fn should_call_parent(type_: TypeRef, name: &str) -> bool {
+    let mut current = type_.parent_type();
+    while let Some(ancestor) = current {
+        // A later redefinition without the setting, e.g. in a modular file,
+        // doesn't lift it, so every definition is checked, newest first.
+        let setting = ancestor.procs.get(name).and_then(|proc| {
+            proc.value.iter().rev().find_map(|value| {
+                settings(value).find(|(setting, _)| *setting == "SpacemanDMM_should_call_parent")
+            })
+        });
+        if let Some((_, value)) = setting {
+            // Macros such as `TRUE` are already expanded; anything that isn't
+            // a constant doesn't turn the check on.
+            return value.is_truthy().unwrap_or(false);
+        }
+        current = ancestor.parent_type();
+    }
+
+    false
+}
+
+/// Classifies the `..()` calls in a proc body. A call is unconditional when it
+/// is made every time the body runs, e.g. by `. = ..()` or `if(!..())` at the
+/// top level, and conditional when it is only made on some paths: in a nested
+/// block, on the right of `&&` or `||`, in a branch of `?:`, or after a
+/// statement that may have returned.
+fn parent_call(code: &[Spanned<Statement>]) -> ParentCall {
+    let mut found = ParentCall::None;
+    let mut may_have_exited = false;
+    for statement in code {
+        let call = statement_parent_call(&statement.elem);
+        found = strongest(
+            found,
+            if may_have_exited {
+                conditional(call)
+            } else {
+                call
+            },
+        );
+        if found == ParentCall::Unconditional || exits(&statement.elem) {
+            // Anything after a top-level `return` is unreachable.
+            break;
+        }
+        may_have_exited |= may_exit(&statement.elem);
+    }
+
+    found
+}
+
+/// The stronger of two classifications: `Unconditional` over `Conditional`
+/// over `None`.
+fn strongest(a: ParentCall, b: ParentCall) -> ParentCall {
+    match (a, b) {
+        (ParentCall::Unconditional, _) | (_, ParentCall::Unconditional) => {
+            ParentCall::Unconditional
+        }
+        (ParentCall::Conditional, _) | (_, ParentCall::Conditional) => ParentCall::Conditional,
+        _ => ParentCall::None,
+    }
+}
+
+/// Demotes the calls in something that is only evaluated on some paths.
+fn conditional(call: ParentCall) -> ParentCall {
+    match call {
+        ParentCall::None => ParentCall::None,
+        _ => ParentCall::Conditional,
+    }
+}
+
+/// Whether `statement` always leaves the proc.
+fn exits(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Return(_) | Statement::Throw(_) | Statement::Crash(_) | Statement::Goto(_)
+    )
+}
+
+/// Whether `statement` leaves the proc on some path. A `return` in a `spawn`
+/// only ends the spawned block.
+fn may_exit(statement: &Statement) -> bool {
+    exits(statement)
+        || !matches!(statement, Statement::Spawn { .. })
+            && nested_blocks(statement)
+                .into_iter()
+                .flatten()
+                .any(|statement| may_exit(&statement.elem))
+}
+
+/// The blocks nested in `statement`, none of which is run every time the
+/// statement is.
+fn nested_blocks(statement: &Statement) -> Vec<&[Spanned<Statement>]> {
+    match statement {
+        Statement::While { block, .. }
+        | Statement::DoWhile { block, .. }
+        | Statement::ForInfinite { block }
+        | Statement::ForLoop { block, .. }
+        | Statement::Spawn { block, .. }
+        | Statement::Label { block, .. } => vec![&**block],
+        Statement::ForList(for_list) => vec![&*for_list.block],
+        Statement::ForRange(for_range) => vec![&*for_range.block],
+        Statement::If { arms, else_arm } => arms
+            .iter()
+            .map(|(_, block)| &**block)
+            .chain(else_arm.as_deref())
+            .collect(),
+        Statement::Switch { cases, default, .. } => cases
+            .iter()
+            .map(|(_, block)| &**block)
+            .chain(default.as_deref())
+            .collect(),
+        Statement::TryCatch {
+            try_block,
+            catch_block,
+            ..
+        } => vec![&**try_block, &**catch_block],
+        _ => vec![],
+    }
+}
+
+fn block_parent_call(block: &[Spanned<Statement>]) -> ParentCall {
+    block
+        .iter()
+        .map(|statement| statement_parent_call(&statement.elem))
+        .fold(ParentCall::None, strongest)
+}
+
+/// Classifies the calls made by `statement` as if it runs unconditionally.
+fn statement_parent_call(statement: &Statement) -> ParentCall {
+    let nested = nested_blocks(statement)
+        .into_iter()
+        .map(block_parent_call)
+        .fold(ParentCall::None, strongest);
+    let evaluated = match statement {
+        Statement::Expr(e) | Statement::Throw(e) | Statement::Del(e) => expression_parent_call(e),
+        Statement::Return(e) | Statement::Crash(e) => optional_parent_call(e.as_ref()),
+        Statement::Var(var) => optional_parent_call(var.value.as_ref()),
+        Statement::Vars(vars) => vars
+            .iter()
+            .map(|var| optional_parent_call(var.value.as_ref()))
+            .fold(ParentCall::None, strongest),
+        Statement::If { arms, .. } => {
+            // Later conditions are only tested if the earlier ones fail.
+            let mut conditions = arms
+                .iter()
+                .map(|(condition, _)| expression_parent_call(&condition.elem));
+            let first = conditions.next().unwrap_or(ParentCall::None);
+            strongest(
+                first,
+                conditional(conditions.fold(ParentCall::None, strongest)),
+            )
+        }
+        Statement::While { condition, .. } => expression_parent_call(condition),
+        // Only tested if the block doesn't leave the loop first.
+        Statement::DoWhile { condition, .. } => {
+            conditional(expression_parent_call(&condition.elem))
+        }
+        Statement::ForLoop {
+            init, test, inc, ..
+        } => strongest(
+            strongest(
+                init.as_deref()
+                    .map_or(ParentCall::None, statement_parent_call),
+                optional_parent_call(test.as_deref()),
+            ),
+            // Only run after an iteration.
+            conditional(
+                inc.as_deref()
+                    .map_or(ParentCall::None, statement_parent_call),
+            ),
+        ),
+        Statement::ForList(for_list) => optional_parent_call(for_list.in_list.as_ref()),
+        Statement::ForRange(for_range) => strongest(
+            strongest(
+                expression_parent_call(&for_range.start),
+                expression_parent_call(&for_range.end),
+            ),
+            optional_parent_call(for_range.step.as_ref()),
+        ),
+        Statement::Spawn { delay, .. } => optional_parent_call(delay.as_ref()),
+        Statement::Switch { input, .. } => expression_parent_call(input),
+        _ => ParentCall::None,
+    };
+
+    strongest(evaluated, conditional(nested))
+}
+
+fn optional_parent_call(expression: Option<&Expression>) -> ParentCall {
+    expression.map_or(ParentCall::None, expression_parent_call)
+}
+
+fn arguments_parent_call(args: &[Expression]) -> ParentCall {
+    args.iter()
+        .map(expression_parent_call)
+        .fold(ParentCall::None, strongest)
+}
+
+fn expression_parent_call(expression: &Expression) -> ParentCall {
+    match expression {
+        Expression::Base { term, follow } => {
+            let mut found = term_parent_call(&term.elem);
+            let mut short_circuited = false;
+            for follow in follow.iter() {
+                let call = follow_parent_call(&follow.elem);
+                found = strongest(
+                    found,
+                    if short_circuited {
+                        conditional(call)
+                    } else {
+                        call
+                    },
+                );
+                // `a?.b(..())` skips the rest of the chain when `a` is null.
+                short_circuited |= matches!(
+                    follow.elem,
+                    Follow::Index(ListAccessKind::Safe, _)
+                        | Follow::Field(
+                            PropertyAccessKind::SafeDot | PropertyAccessKind::SafeColon,
+                            _
+                        )
+                        | Follow::Call(
+                            PropertyAccessKind::SafeDot | PropertyAccessKind::SafeColon,
+                            _,
+                            _
+                        )
+                );
+            }
+            found
+        }
+        Expression::BinaryOp {
+            op: BinaryOp::And | BinaryOp::Or,
+            lhs,
+            rhs,
+        }
+        | Expression::AssignOp {
+            op: AssignOp::AndAssign | AssignOp::OrAssign,
+            lhs,
+            rhs,
+        } => strongest(
+            expression_parent_call(lhs),
+            conditional(expression_parent_call(rhs)),
+        ),
+        Expression::BinaryOp { lhs, rhs, .. } | Expression::AssignOp { lhs, rhs, .. } => {
+            strongest(expression_parent_call(lhs), expression_parent_call(rhs))
+        }
+        Expression::TernaryOp { cond, if_, else_ } => strongest(
+            expression_parent_call(cond),
+            conditional(strongest(
+                expression_parent_call(if_),
+                expression_parent_call(else_),
+            )),
+        ),
+    }
+}
+
+fn term_parent_call(term: &Term) -> ParentCall {
+    match term {
+        Term::ParentCall(_) => ParentCall::Unconditional,
+        Term::Expr(e) => expression_parent_call(e),
+        Term::Call(_, args)
+        | Term::SelfCall(args)
+        | Term::GlobalCall(_, args)
+        | Term::List(args) => arguments_parent_call(args),
+        Term::NewImplicit { args }
+        | Term::NewPrefab { args, .. }
+        | Term::NewMiniExpr { args, .. } => args
+            .as_deref()
+            .map_or(ParentCall::None, arguments_parent_call),
+        Term::DynamicCall(target, args) => {
+            strongest(arguments_parent_call(target), arguments_parent_call(args))
+        }
+        Term::ExternalCall {
+            library,
+            function,
+            args,
+        } => strongest(
+            strongest(
+                optional_parent_call(library.as_deref()),
+                expression_parent_call(function),
+            ),
+            arguments_parent_call(args),
+        ),
+        Term::Input { args, in_list, .. } | Term::Locate { args, in_list } => strongest(
+            arguments_parent_call(args),
+            optional_parent_call(in_list.as_deref()),
+        ),
+        Term::Pick(choices) => choices
+            .iter()
+            .map(|(weight, choice)| {
+                strongest(
+                    optional_parent_call(weight.as_ref()),
+                    expression_parent_call(choice),
+                )
+            })
+            .fold(ParentCall::None, strongest),
+        Term::InterpString(_, parts) => parts
+            .iter()
+            .map(|(part, _)| optional_parent_call(part.as_ref()))
+            .fold(ParentCall::None, strongest),
+        // Literals, identifiers and prefabs, whose vars are constant.
+        _ => ParentCall::None,
+    }
+}
+
+fn follow_parent_call(follow: &Follow) -> ParentCall {
+    match follow {
+        Follow::Index(_, index) => expression_parent_call(index),
+        Follow::Call(_, _, args) => arguments_parent_call(args),
+        _ => ParentCall::None,
+    }
+}
+
+fn extract_param(param: &Parameter) -> ParsedParam {
+    ParsedParam {
+        name: param.name.to_string(),
+        declared_type: if param.var_type.type_path.is_empty() {
+            None
+        } else {
+            Some(param.var_type.type_path.join("/"))
+        },
+        default: param.default.as_ref().map(|expr| expr.to_string()),
+        input_type: param.input_type.map(|input_type| input_type.to_string()),
+        in_list: param.in_list.as_ref().map(|expr| expr.to_string()),
+    }
+}
+
+fn extract_var(ctx: &Context, root: &Path, type_: TypeRef, name: &str, var: &TypeVar) -> ParsedVar {
+    // `var/x` declares no type at all, like an untyped parameter.
+    let declared_type = var
+        .declaration
+        .as_ref()
+        .map(|var_decl| &var_decl.var_type.type_path)
+        .filter(|type_path| !type_path.is_empty())
+        .map(|type_path| type_path.join("/"));
+
+    let flags = var
+        .declaration
+        .as_ref()
+        .or_else(|| type_.get_var_declaration(name))
+        .map(|var_decl| {
+            let flags = var_decl.var_type.flags;
+            VarFlags {
+                is_const: flags.is_const(),
+                is_static: flags.is_static(),
+                is_tmp: flags.is_tmp(),
+                is_final: flags.is_final(),
+            }
+        })
+        .unwrap_or_default();
+
+    let (value_kind, json_const_val, expression) =
+        match (&var.value.constant, &var.value.expression) {
+            (Some(constant), _) => (VarValueKind::Constant, constant::to_json(constant), None),
+            (None, Some(expression)) => (
+                VarValueKind::Expression,
+                String::new(),
+                Some(expression.to_string()),
+            ),
+            (None, None) => (VarValueKind::None, String::new(), None),
+        };
+
+    ParsedVar {
+        name: name.to_owned(),
+        location: extract_location(ctx, root, var.value.location),
+        declared_type,
+        flags,
+        value_kind,
+        json_const_val,
+        expression,
+    }
+}
+
+/// Collects the errors and warnings raised while parsing the environment that
+/// was checked out under `root`.
+pub(crate) fn extract_diagnostics(ctx: &Context, root: &Path) -> Vec<ParsedDiagnostic> {
+    ctx.errors()
+        .iter()
+        .map(|error| {
+            let location = error.location();
+            let file = if location.is_builtins() {
+                "(builtins)".to_owned()
+            } else {
+                relative_path(ctx, root, location.file)
+            };
+
+            ParsedDiagnostic {
+                severity: match error.severity() {
+                    Severity::Error => DiagnosticSeverity::Error,
+                    Severity::Warning => DiagnosticSeverity::Warning,
+                    Severity::Info => DiagnosticSeverity::Info,
+                    Severity::Hint => DiagnosticSeverity::Hint,
+                },
+                file,
+                line: location.line,
+                column: location.column,
+                message: error.description().to_owned(),
+                code: error.errortype().map(str::to_owned),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dme::parse_for_test;
+
+    #[test]
+    fn only_parent_type_set_in_code_is_explicit() {
+        let tree = parse_for_test(
+            r#"
+/datum/base
+/datum/child
+	parent_type = /datum/base
+/datum/base/implied
+"#,
+        );
+        assert!(explicit_parent(tree.find("/datum/child").unwrap()));
+        assert!(!explicit_parent(tree.find("/datum/base/implied").unwrap()));
+        assert!(!explicit_parent(tree.find("/datum").unwrap()));
+        assert!(!explicit_parent(tree.find("/obj").unwrap()));
+    }
+
+    fn classify(body: &str) -> ParentCall {
+        let tree = parse_for_test(&format!("/datum/proc/test()\n{}\n", body));
+        let datum = tree.find("/datum").unwrap();
+        let code = datum.procs["test"].value.last().unwrap().code.as_deref();
+        parent_call(code.unwrap())
+    }
+
+    #[test]
+    fn top_level_parent_calls_are_unconditional() {
+        assert_eq!(classify("\t. = ..()"), ParentCall::Unconditional);
+        assert_eq!(
+            classify("\tif(!..())\n\t\treturn"),
+            ParentCall::Unconditional
+        );
+        assert_eq!(
+            classify("\tvar/list/L = list(..())"),
+            ParentCall::Unconditional
+        );
+        assert_eq!(
+            classify("\tvar/list/L = list()\n\tL[..()] = 1"),
+            ParentCall::Unconditional
+        );
+        assert_eq!(classify("\treturn ..()"), ParentCall::Unconditional);
+    }
+
+    #[test]
+    fn parent_calls_on_some_paths_are_conditional() {
+        assert_eq!(classify("\tif(src)\n\t\t..()"), ParentCall::Conditional);
+        assert_eq!(
+            classify("\tif(!src)\n\t\treturn\n\t..()"),
+            ParentCall::Conditional
+        );
+        assert_eq!(classify("\tsrc && ..()"), ParentCall::Conditional);
+        assert_eq!(classify("\t. = src ? ..() : null"), ParentCall::Conditional);
+        assert_eq!(
+            classify("\tif(src)\n\t\tif(..())\n\t\t\treturn"),
+            ParentCall::Conditional
+        );
+    }
+
+    #[test]
+    fn missing_and_unreachable_parent_calls_are_none() {
+        assert_eq!(classify("\treturn 1"), ParentCall::None);
+        assert_eq!(classify("\treturn\n\t..()"), ParentCall::None);
+    }
+
+    #[test]
+    fn should_call_parent_evaluates_the_setting() {
+        let tree = parse_for_test(
+            r#"
+#define TRUE 1
+#define FALSE 0
+/datum/proc/on()
+	set SpacemanDMM_should_call_parent = TRUE
+/datum/proc/off()
+	set SpacemanDMM_should_call_parent = FALSE
+/datum/proc/unset()
+/datum/child/on()
+/datum/child/off()
+/datum/child/unset()
+"#,
+        );
+        let child = tree.find("/datum/child").unwrap();
+        assert!(should_call_parent(child, "on"));
+        assert!(!should_call_parent(child, "off"));
+        assert!(!should_call_parent(child, "unset"));
+    }
+
+    #[test]
+    fn should_call_parent_survives_redefinition() {
+        let tree = parse_for_test(
+            r#"
+/datum/proc/foo()
+	set SpacemanDMM_should_call_parent = 1
+/datum/foo()
+	return
+/datum/child/foo()
+"#,
+        );
+        let child = tree.find("/datum/child").unwrap();
+        assert!(should_call_parent(child, "foo"));
+    }
+}
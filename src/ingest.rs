@@ -0,0 +1,252 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::AtomicUsize},
+};
+
+use git2::{Oid, Repository};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect};
+use slog::{Logger, info, warn};
+use tokio::sync::Semaphore;
+
+use crate::{
+    Args, IngesterError,
+    cache::Cache,
+    config::CodebaseConfig,
+    dme::detect_dme,
+    models::{commit_log_from_diff, find_or_create_codebase, first_parent_diff, git_log_entry},
+    parse_cache::ParseCache,
+    select,
+    worker::{Job, Worker},
+    writer::{self, WriteOptions},
+};
+
+use git_log_entry::Entity as GitLogEntry;
+
+/// Ingests `codebase`. Given `only`, just those commits are walked, starting
+/// from themselves rather than the codebase's `refpath`.
+pub(crate) async fn ingest_codebase(
+    db: &DatabaseConnection,
+    codebase: &CodebaseConfig,
+    scratch_dir: &Path,
+    parse_cache_dir: Option<&Path>,
+    only: Option<&HashSet<Oid>>,
+    args: &Args,
+    logger: &Logger,
+) -> Result<(), IngesterError> {
+    info!(logger, "ingesting codebase {}", codebase.name);
+    let codebase_id = find_or_create_codebase(db, &codebase.name).await?;
+
+    let repo_root = PathBuf::from(&codebase.repo_root);
+    let repo = Repository::open(&repo_root)?;
+    let scratch_dir = scratch_dir.join(&codebase.name);
+
+    let mut revwalk = repo.revwalk()?;
+    if let Some(only) = only {
+        for &oid in only.iter() {
+            if repo.find_commit(oid).is_ok() {
+                revwalk.push(oid)?;
+            } else {
+                warn!(logger, "{} is not in the repository, skipping it", oid);
+            }
+        }
+    } else if args.range.is_empty() {
+        revwalk.push_ref(&codebase.refpath)?;
+    } else {
+        for range in args.range.iter() {
+            revwalk.push_range(range)?;
+        }
+    }
+    // Oldest first, so each snapshot's `seq` follows history and validity
+    // intervals grow forwards. That only holds across runs if each run adds
+    // commits newer than those already ingested.
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME | git2::Sort::REVERSE)?;
+    if args.every.is_some() {
+        // Sample the mainline, not whatever was merged into it on the day.
+        revwalk.simplify_first_parent()?;
+    }
+
+    // Commits that are already ingested or queued, and the first of them with
+    // each tree. Parents come before their children, so later commits can
+    // share their snapshots.
+    let mut known = HashSet::new();
+    let mut trees = HashMap::new();
+    let ingested: Vec<(String, String)> = GitLogEntry::find()
+        .select_only()
+        .column(git_log_entry::Column::CommitHash)
+        .column(git_log_entry::Column::TreeHash)
+        .filter(git_log_entry::Column::CodebaseId.eq(codebase_id))
+        .into_tuple()
+        .all(db)
+        .await?;
+    for (commit_hash, tree_hash) in ingested.iter() {
+        let (Ok(oid), Ok(tree_id)) = (Oid::from_str(commit_hash), Oid::from_str(tree_hash)) else {
+            continue;
+        };
+        known.insert(oid);
+        trees.entry(tree_id).or_insert(oid);
+    }
+    let ingested = known.clone();
+    if args.incremental {
+        // Hiding ingested commits hides their ancestors too, so only history
+        // added since then is walked.
+        for &oid in ingested.iter() {
+            if repo.find_commit(oid).is_ok() {
+                revwalk.hide(oid)?;
+            }
+        }
+    }
+
+    let dme = match &codebase.dme {
+        Some(dme) => dme.clone(),
+        None => detect_dme(&repo.find_reference(&codebase.refpath)?.peel_to_tree()?)?,
+    };
+    info!(logger, "using environment {}", dme);
+    let parse_cache = parse_cache_dir
+        .map(|dir| ParseCache::new(dir, &dme))
+        .transpose()?;
+
+    info!(logger, "walking revisions");
+
+    let pathspec = select::pathspec(args)?;
+    let mut selected = vec![];
+    let mut dates = vec![];
+    let mut already_ingested = vec![];
+    for oid in revwalk.flatten() {
+        if only.is_some_and(|only| !only.contains(&oid)) {
+            continue;
+        }
+        let commit = repo.find_commit(oid)?;
+        if !select::in_date_range(&commit, args) {
+            continue;
+        }
+        // Ingested commits are never diffed again; they go on to sampling so
+        // a period that already has one isn't sampled again.
+        let mut log = None;
+        if let Some(pathspec) = &pathspec
+            && !ingested.contains(&oid)
+        {
+            let diff = first_parent_diff(&repo, &commit)?;
+            if !select::touches(pathspec, &diff)? {
+                continue;
+            }
+            log = Some(commit_log_from_diff(&commit, &diff)?);
+        }
+        dates.push(select::commit_date(&commit));
+        already_ingested.push(ingested.contains(&oid));
+        selected.push((commit, log));
+    }
+    if let Some(every) = args.every {
+        selected = select::sample(selected, &dates, &already_ingested, every);
+    }
+
+    let mut commits = vec![];
+    let mut aliases = 0;
+    for (commit, log) in selected {
+        let oid = commit.id();
+        known.insert(oid);
+        trees.entry(commit.tree_id()).or_insert(oid);
+
+        if ingested.contains(&oid) {
+            if args.log_skipped_commits {
+                let dt = chrono::DateTime::from_timestamp(commit.time().seconds(), 0).unwrap();
+                info!(
+                    logger,
+                    "skipping {} @{}, {}",
+                    dme,
+                    oid,
+                    dt.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+            continue;
+        }
+
+        let alias_of = trees
+            .get(&commit.tree_id())
+            .copied()
+            .filter(|&earlier| earlier != oid);
+        if alias_of.is_some() {
+            aliases += 1;
+        }
+        // Whether the commit changes DM sources is only known once a worker
+        // has diffed it against this parent.
+        let parent = commit
+            .parent_id(0)
+            .ok()
+            .filter(|parent| known.contains(parent));
+        commits.push(Job {
+            oid,
+            alias_of,
+            parent,
+            log,
+        });
+    }
+
+    let workers = args
+        .workers
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        })
+        .max(1);
+    info!(
+        logger,
+        "ingesting {} commits ({} with the tree of an earlier one) with {} workers",
+        commits.len(),
+        aliases,
+        workers
+    );
+
+    let commit_count = commits.len();
+    let parse_ahead = args.parse_ahead.unwrap_or(workers * 2).max(1);
+    let commits = Arc::new(commits);
+    let next = Arc::new(AtomicUsize::new(0));
+    let window = Arc::new(Semaphore::new(parse_ahead));
+    let (tx, rx) = tokio::sync::mpsc::channel(parse_ahead);
+
+    // A name listed twice would be resolved, and written, twice.
+    let mut inherited_vars = codebase.inherited_vars.clone();
+    inherited_vars.sort();
+    inherited_vars.dedup();
+    let writer = tokio::spawn(writer::run(
+        db.clone(),
+        Cache::new(),
+        codebase_id,
+        rx,
+        WriteOptions {
+            batch_size: args.batch_size.max(1),
+            inherited_vars,
+        },
+        logger.clone(),
+    ));
+
+    let mut handles = vec![];
+    for i in 0..workers {
+        let worker = Worker::new(
+            &repo_root,
+            scratch_dir.join(format!("worker-{}", i)),
+            dme.clone(),
+            parse_cache.clone(),
+            logger.clone(),
+        )?;
+        handles.push(tokio::spawn(worker.run(
+            commits.clone(),
+            next.clone(),
+            window.clone(),
+            tx.clone(),
+        )));
+    }
+    drop(tx);
+
+    let written = writer.await??;
+    for handle in handles {
+        handle.await?;
+    }
+    if written != commit_count {
+        return Err(IngesterError::Worker(format!(
+            "only {} of {} commits were parsed",
+            written, commit_count
+        )));
+    }
+
+    Ok(())
+}
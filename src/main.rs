@@ -1,12 +1,7 @@
 use std::fmt::Debug;
 
 use clap::Parser;
-use dreammaker::detect_environment;
-use git2::Repository;
-use sea_orm::{
-    ActiveValue::Set, ColumnTrait, ConnectOptions, Database, EntityTrait, QueryFilter,
-    TransactionTrait,
-};
+use sea_orm::{ConnectOptions, Database};
 
 use slog::info;
 use sloggers::{
@@ -18,19 +13,27 @@ use thiserror::Error;
 
 mod cache;
 mod config;
+mod constant;
+mod convert;
 mod dme;
+mod extract;
+mod ingest;
 mod models;
-
-use models::git_log_entry;
-use models::git_log_entry::Entity as GitLogEntry;
+mod parse_cache;
+mod select;
+mod source;
+mod validity;
+mod worker;
+mod writer;
 
 use crate::{
-    cache::Cache,
     config::Config,
-    dme::get_object_tree,
+    ingest::ingest_codebase,
     models::{
-        git_commit_log_numstat_entry, log_entry_from_commit, proc_decl, proc_decl_snapshot,
-        snapshot, type_decl, type_decl_snapshot, var_decl, var_decl_snapshot,
+        codebase, diagnostic, git_commit_log_numstat_entry, git_log_entry, proc_decl,
+        proc_decl_validity, proc_param, proc_signature, snapshot, source_file, type_closure,
+        type_decl, type_decl_validity, var_decl, var_decl_validity, var_effective_validity,
+        var_value,
     },
 };
 
@@ -40,24 +43,75 @@ enum IngesterError {
     Parser(String),
     #[error("cache error")]
     Cache(String),
+    #[error("worker error")]
+    Worker(String),
+    #[error("invalid arguments")]
+    Args(String),
     #[error(transparent)]
     Db(#[from] sea_orm::DbErr),
     #[error(transparent)]
     Repo(#[from] git2::Error),
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
 }
 
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(long)]
     settings: String,
+    /// Only ingest the named codebase. May be given more than once; all
+    /// configured codebases are ingested if left out.
+    #[arg(long)]
+    codebase: Vec<String>,
+    /// Walk this full ref path instead of the codebase's `refpath`. Only
+    /// allowed when a single codebase is ingested.
     #[arg(long)]
-    refpath: String,
+    refpath: Option<String>,
     #[arg(long, required = false, num_args = 0, action)]
     create_tables: bool,
+    /// Convert a database written before codebases existed: its tables are
+    /// replaced by the current ones, and the commits it had snapshots of are
+    /// ingested again into the codebase picked with `--codebase`.
+    #[arg(long, required = false, num_args = 0, action)]
+    convert: bool,
     #[arg(long, required = false, num_args = 0, action)]
     log_skipped_commits: bool,
+    /// Only walk history added since the last run, skipping the ancestors of
+    /// every ingested commit instead of checking each of them. Commits on
+    /// merged branches older than an ingested commit are never picked up.
+    #[arg(long, required = false, num_args = 0, action)]
+    incremental: bool,
+    /// Walk the given revision range, e.g. `v1.0..master`, instead of the
+    /// codebase's `refpath`. May be given more than once. Only allowed when a
+    /// single codebase is ingested.
+    #[arg(long)]
+    range: Vec<String>,
+    /// Only ingest commits made on or after this date, e.g. `2020-01-01`.
+    #[arg(long)]
+    since: Option<chrono::NaiveDate>,
+    /// Only ingest commits made on or before this date.
+    #[arg(long)]
+    until: Option<chrono::NaiveDate>,
+    /// Only ingest the last commit of each `day` or `week`, or of each run of
+    /// the given number of commits, following first parents only.
+    #[arg(long)]
+    every: Option<select::Every>,
+    /// Only ingest commits that change a path matching this glob, e.g.
+    /// `code/modules/mob/**`. May be given more than once.
+    #[arg(long)]
+    path: Vec<String>,
+    /// Number of commits to parse in parallel. Defaults to the number of CPUs.
+    #[arg(long)]
+    workers: Option<usize>,
+    /// Maximum number of commits parsed ahead of the database writer.
+    /// Defaults to twice the number of workers.
+    #[arg(long)]
+    parse_ahead: Option<usize>,
+    /// Maximum number of rows per multi-row insert into the snapshot tables.
+    #[arg(long, default_value_t = 1000)]
+    batch_size: usize,
 }
 
 #[tokio::main]
@@ -66,133 +120,107 @@ async fn main() -> Result<(), IngesterError> {
     builder.level(Severity::Debug);
     builder.destination(Destination::Stderr);
 
-    let mut cache = Cache::new();
-
     let logger = builder.build().unwrap();
 
     let args = Args::parse();
-    let settings = std::fs::read_to_string(args.settings).expect("could not read settings file");
-    let config: Config = toml::from_str(&settings).expect("could not parse settings");
+    let settings = std::fs::read_to_string(&args.settings).expect("could not read settings file");
+    let mut config: Config = toml::from_str(&settings).expect("could not parse settings");
+    config.upgrade_legacy();
+
+    for name in args.codebase.iter() {
+        if !config
+            .codebases
+            .iter()
+            .any(|codebase| &codebase.name == name)
+        {
+            return Err(IngesterError::Args(format!(
+                "no codebase named `{}` in settings",
+                name
+            )));
+        }
+    }
+    let mut codebases: Vec<_> = config
+        .codebases
+        .into_iter()
+        .filter(|codebase| args.codebase.is_empty() || args.codebase.contains(&codebase.name))
+        .collect();
+    if let Some(refpath) = &args.refpath {
+        let [codebase] = codebases.as_mut_slice() else {
+            return Err(IngesterError::Args(
+                "--refpath needs a single codebase, pick one with --codebase".into(),
+            ));
+        };
+        codebase.refpath = refpath.clone();
+    }
+    if !args.range.is_empty() && codebases.len() != 1 {
+        return Err(IngesterError::Args(
+            "--range needs a single codebase, pick one with --codebase".into(),
+        ));
+    }
+    if args.convert && codebases.len() != 1 {
+        return Err(IngesterError::Args(
+            "--convert needs a single codebase, pick one with --codebase".into(),
+        ));
+    }
 
     let opt = ConnectOptions::new(config.integrations.db_connection_string);
     let db = Database::connect(opt).await?;
 
-    if args.create_tables {
+    // The legacy tables share names with current ones, so they go first.
+    let legacy_commits = if args.convert {
+        Some(convert::take_legacy_commits(&db, &logger).await?)
+    } else {
+        None
+    };
+
+    if args.create_tables || args.convert {
         info!(logger, "creating tables");
         db.get_schema_builder()
+            .register(codebase::Entity)
+            .register(source_file::Entity)
             .register(type_decl::Entity)
             .register(var_decl::Entity)
+            .register(var_value::Entity)
             .register(proc_decl::Entity)
+            .register(proc_signature::Entity)
+            .register(proc_param::Entity)
             .register(git_log_entry::Entity)
             .register(git_commit_log_numstat_entry::Entity)
-            .register(proc_decl_snapshot::Entity)
+            .register(proc_decl_validity::Entity)
             .register(snapshot::Entity)
-            .register(type_decl_snapshot::Entity)
-            .register(var_decl_snapshot::Entity)
+            .register(type_decl_validity::Entity)
+            .register(type_closure::Entity)
+            .register(var_decl_validity::Entity)
+            .register(var_effective_validity::Entity)
+            .register(diagnostic::Entity)
             .apply(&db)
             .await?;
     }
 
-    let repo = Repository::open(&config.environment.repo_root)?;
-    let mut revwalk = repo.revwalk()?;
-    revwalk.push_ref(&args.refpath)?;
-    revwalk.set_sorting(git2::Sort::TIME)?;
-
-    info!(logger, "walking revisions");
-
-    for oid in revwalk.flatten() {
-        let object = repo.find_object(oid, None)?;
-        repo.reset(&object, git2::ResetType::Hard, None)?;
-        let path = std::path::Path::new(&config.environment.repo_root);
-
-        if let Ok(dme) = detect_environment(path, "paradise.dme")
-            && let Some(dme_path) = dme
-            && let Some(commit) = object.as_commit()
-        {
-            let dt = chrono::DateTime::from_timestamp(commit.time().seconds(), 0).unwrap();
-            let txn = db.begin().await?;
-
-            if (GitLogEntry::find()
-                .filter(git_log_entry::Column::CommitHash.eq(commit.id().to_string()))
-                .one(&txn)
-                .await?)
-                .is_some()
-            {
-                if args.log_skipped_commits {
-                    info!(
-                        logger,
-                        "skipping {} @{}, {}",
-                        dme_path.to_string_lossy(),
-                        oid,
-                        dt.format("%Y-%m-%d %H:%M:%S")
-                    );
-                }
-                continue;
-            }
-
-            let log_entry_id = log_entry_from_commit(&txn, &repo, commit).await?;
-
-            info!(
-                logger,
-                "parsing {} @{}, {}",
-                dme_path.to_string_lossy(),
-                oid,
-                dt.format("%Y-%m-%d %H:%M:%S")
-            );
-            let tree = get_object_tree(dme_path)?;
-
-            let snapshot = snapshot::ActiveModel {
-                git_log_entry_id: Set(log_entry_id),
-                ..Default::default()
-            };
-            let snapshot_insert = snapshot::Entity::insert(snapshot).exec(&txn).await?;
-            let snapshot_id = snapshot_insert.last_insert_id;
-
-            let mut count = 0;
-            for type_ in tree.iter_types() {
-                let td = cache.get_type(&type_.path, &txn).await?;
-
-                let tds = type_decl_snapshot::ActiveModel {
-                    snapshot_id: Set(snapshot_id),
-                    type_decl_id: Set(td.id),
-                };
-
-                type_decl_snapshot::Entity::insert(tds).exec(&txn).await?;
-
-                for (name, _) in type_.procs.iter() {
-                    let proc_name = format!("{}/{}", type_.path, name);
-                    let pd = cache.get_proc(&proc_name, &txn).await?;
-                    let pds = proc_decl_snapshot::ActiveModel {
-                        snapshot_id: Set(snapshot_id),
-                        proc_decl_id: Set(pd.id),
-                    };
-
-                    proc_decl_snapshot::Entity::insert(pds)
-                        .on_conflict_do_nothing() // multiple defs of the same proc are fine
-                        .exec(&txn)
-                        .await?;
-                }
-
-                for (name, var) in type_.vars.iter() {
-                    let var_path = format!("{}/{}", type_.path, name);
-                    let vd = cache.get_var_decl(&var_path, var, &txn).await?;
-
-                    let vds = var_decl_snapshot::ActiveModel {
-                        snapshot_id: Set(snapshot_id),
-                        var_decl_id: Set(vd.id),
-                    };
-
-                    var_decl_snapshot::Entity::insert(vds).exec(&txn).await?;
-                }
-                count += 1;
-                if count % 1000 == 0 {
-                    info!(logger, "{} paths", count)
-                }
-            }
-
-            info!(logger, "committing transaction");
-            txn.commit().await?;
-        }
+    let scratch_dir = config
+        .environment
+        .scratch_dir
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("ss13_codedb"));
+    let parse_cache_dir = config
+        .environment
+        .parse_cache_dir
+        .map(std::path::PathBuf::from);
+
+    for codebase in codebases.iter() {
+        ingest_codebase(
+            &db,
+            codebase,
+            &scratch_dir,
+            parse_cache_dir.as_deref(),
+            legacy_commits.as_ref(),
+            &args,
+            &logger,
+        )
+        .await?;
+    }
+    if legacy_commits.is_some() {
+        convert::finish(&db).await?;
     }
 
     Ok(())
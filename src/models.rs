@@ -1,7 +1,33 @@
+use std::hash::Hash;
+
 use git2::DiffStatsFormat;
-use sea_orm::{ActiveValue::Set, DatabaseTransaction, EntityTrait};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, DatabaseConnection,
+    DatabaseTransaction, EntityTrait, QueryFilter,
+};
+
+use crate::{IngesterError, source::touches_sources};
+
+pub mod codebase {
+    use sea_orm::DeriveEntityModel;
+    use sea_orm::entity::prelude::*;
+
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "codebase")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        #[sea_orm(unique)]
+        name: String,
+        #[sea_orm(has_many)]
+        pub git_log_entries: HasMany<super::git_log_entry::Entity>,
+        #[sea_orm(has_many)]
+        pub snapshots: HasMany<super::snapshot::Entity>,
+    }
 
-use crate::IngesterError;
+    impl ActiveModelBehavior for ActiveModel {}
+}
 
 pub mod git_log_entry {
     use chrono::Utc;
@@ -14,6 +40,9 @@ pub mod git_log_entry {
     pub struct Model {
         #[sea_orm(primary_key)]
         pub id: i32,
+        pub codebase_id: i32,
+        #[sea_orm(belongs_to, from = "codebase_id", to = "id")]
+        codebase: HasOne<super::codebase::Entity>,
         commit_hash: String,
         tree_hash: String,
         parent_hashes: String,
@@ -60,21 +89,89 @@ pub mod snapshot {
     use sea_orm::DeriveEntityModel;
     use sea_orm::entity::prelude::*;
 
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+    #[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+    pub enum SnapshotStatus {
+        /// The environment parsed and its decls were recorded.
+        #[sea_orm(string_value = "ok")]
+        Ok,
+        /// The environment failed to parse; no decls were recorded.
+        #[sea_orm(string_value = "failed")]
+        Failed,
+        /// The commit has no environment to parse.
+        #[sea_orm(string_value = "skipped")]
+        Skipped,
+    }
+
     #[sea_orm::model]
     #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
     #[sea_orm(table_name = "snapshot")]
     pub struct Model {
         #[sea_orm(primary_key)]
-        id: i32,
+        pub id: i32,
+        pub codebase_id: i32,
+        #[sea_orm(belongs_to, from = "codebase_id", to = "id")]
+        codebase: HasOne<super::codebase::Entity>,
         pub git_log_entry_id: i32,
         #[sea_orm(belongs_to, from = "git_log_entry_id", to = "id")]
         git_log_entry: HasOne<super::git_log_entry::Entity>,
-        #[sea_orm(has_many, via = "type_decl_snapshot")]
-        pub type_decls: HasMany<super::type_decl::Entity>,
-        #[sea_orm(has_many, via = "proc_decl_snapshot")]
-        pub proc_decls: HasMany<super::proc_decl::Entity>,
-        #[sea_orm(has_many, via = "var_decl_snapshot")]
-        pub var_decls: HasMany<super::var_decl::Entity>,
+        pub status: SnapshotStatus,
+        #[sea_orm(column_type = "Text", nullable)]
+        pub error: Option<String>,
+        /// Position of an `ok` snapshot among its codebase's `ok` snapshots, in
+        /// the order they were written. Validity intervals are ranges of it.
+        /// `None` for failed and skipped snapshots.
+        pub seq: Option<i32>,
+        /// The snapshot whose decls this one shares, because the commit's DM
+        /// sources are identical to that commit's. Aliases have the same
+        /// `status`, `error` and `seq` as their target and no rows of their
+        /// own.
+        pub alias_of: Option<i32>,
+        #[sea_orm(self_ref, relation_enum = "AliasOf", from = "alias_of", to = "id")]
+        pub alias: HasOne<Entity>,
+        #[sea_orm(has_many)]
+        pub diagnostics: HasMany<super::diagnostic::Entity>,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod diagnostic {
+    use sea_orm::DeriveEntityModel;
+    use sea_orm::entity::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(
+        Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+    )]
+    #[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+    pub enum DiagnosticSeverity {
+        #[sea_orm(string_value = "error")]
+        Error,
+        #[sea_orm(string_value = "warning")]
+        Warning,
+        #[sea_orm(string_value = "info")]
+        Info,
+        #[sea_orm(string_value = "hint")]
+        Hint,
+    }
+
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "diagnostic")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        id: i32,
+        pub snapshot_id: i32,
+        #[sea_orm(belongs_to, from = "snapshot_id", to = "id")]
+        snapshot: HasOne<super::snapshot::Entity>,
+        severity: DiagnosticSeverity,
+        file: String,
+        line: i32,
+        column: i32,
+        #[sea_orm(column_type = "Text")]
+        message: String,
+        code: Option<String>,
     }
 
     impl ActiveModelBehavior for ActiveModel {}
@@ -91,29 +188,148 @@ pub mod type_decl {
         #[sea_orm(primary_key)]
         pub id: i32,
         path: String,
-        #[sea_orm(has_many, via = "type_decl_snapshot")]
-        pub snapshots: HasMany<super::snapshot::Entity>,
     }
 
     impl ActiveModelBehavior for ActiveModel {}
 }
 
-pub mod type_decl_snapshot {
-    use sea_orm::DeriveEntityModel;
+pub mod type_decl_validity {
     use sea_orm::entity::prelude::*;
+    use sea_orm::{Condition, DeriveEntityModel};
 
+    /// A type that existed with the same attributes in every `ok` snapshot of
+    /// a codebase from `first_seq` to `last_seq`.
     #[sea_orm::model]
     #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
-    #[sea_orm(table_name = "type_decl_snapshot")]
+    #[sea_orm(table_name = "type_decl_validity")]
     pub struct Model {
         #[sea_orm(primary_key, auto_increment = false)]
-        pub snapshot_id: i32,
+        pub codebase_id: i32,
         #[sea_orm(primary_key, auto_increment = false)]
         pub type_decl_id: i32,
-        #[sea_orm(belongs_to, from = "snapshot_id", to = "id")]
-        pub snapshot: Option<super::snapshot::Entity>,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub first_seq: i32,
+        pub last_seq: i32,
+        #[sea_orm(belongs_to, from = "codebase_id", to = "id")]
+        pub codebase: Option<super::codebase::Entity>,
         #[sea_orm(belongs_to, from = "type_decl_id", to = "id")]
         pub type_decl: Option<super::type_decl::Entity>,
+        /// The effective parent, taking `parent_type` into account.
+        pub parent_type_id: Option<i32>,
+        #[sea_orm(
+            belongs_to,
+            relation_enum = "ParentType",
+            from = "parent_type_id",
+            to = "id"
+        )]
+        pub parent_type: Option<super::type_decl::Entity>,
+        /// Whether the parent was set with `parent_type`.
+        pub explicit_parent: bool,
+        pub source_file_id: Option<i32>,
+        #[sea_orm(belongs_to, from = "source_file_id", to = "id")]
+        pub source_file: Option<super::source_file::Entity>,
+        pub line: Option<i32>,
+        pub column: Option<i32>,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+
+    impl super::Validity for Entity {
+        type Key = i32;
+
+        const CODEBASE_ID: Column = Column::CodebaseId;
+        const FIRST_SEQ: Column = Column::FirstSeq;
+        const LAST_SEQ: Column = Column::LastSeq;
+
+        fn key(model: &Model) -> i32 {
+            model.type_decl_id
+        }
+
+        fn key_condition(keys: &[i32]) -> Condition {
+            Condition::all().add(Column::TypeDeclId.is_in(keys.iter().copied()))
+        }
+    }
+}
+
+pub mod type_closure {
+    use sea_orm::entity::prelude::*;
+    use sea_orm::{Condition, DeriveEntityModel};
+
+    /// An ancestor/descendant pair in the type tree of every `ok` snapshot of a
+    /// codebase from `first_seq` to `last_seq`, following effective parents.
+    /// Each type is its own ancestor at depth 0, so a subtree including its
+    /// root is a single lookup on `ancestor_type_decl_id`.
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "type_closure")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub codebase_id: i32,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub ancestor_type_decl_id: i32,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub descendant_type_decl_id: i32,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub first_seq: i32,
+        pub last_seq: i32,
+        #[sea_orm(belongs_to, from = "codebase_id", to = "id")]
+        pub codebase: Option<super::codebase::Entity>,
+        #[sea_orm(
+            belongs_to,
+            relation_enum = "Ancestor",
+            from = "ancestor_type_decl_id",
+            to = "id"
+        )]
+        pub ancestor: Option<super::type_decl::Entity>,
+        #[sea_orm(
+            belongs_to,
+            relation_enum = "Descendant",
+            from = "descendant_type_decl_id",
+            to = "id"
+        )]
+        pub descendant: Option<super::type_decl::Entity>,
+        /// Number of parent steps from the descendant up to the ancestor.
+        pub depth: i32,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+
+    impl super::Validity for Entity {
+        type Key = (i32, i32);
+
+        const CODEBASE_ID: Column = Column::CodebaseId;
+        const FIRST_SEQ: Column = Column::FirstSeq;
+        const LAST_SEQ: Column = Column::LastSeq;
+
+        fn key(model: &Model) -> (i32, i32) {
+            (model.ancestor_type_decl_id, model.descendant_type_decl_id)
+        }
+
+        fn key_condition(keys: &[(i32, i32)]) -> Condition {
+            keys.iter()
+                .fold(Condition::any(), |condition, (ancestor, descendant)| {
+                    condition.add(
+                        Condition::all()
+                            .add(Column::AncestorTypeDeclId.eq(*ancestor))
+                            .add(Column::DescendantTypeDeclId.eq(*descendant)),
+                    )
+                })
+        }
+    }
+}
+
+pub mod source_file {
+    use sea_orm::DeriveEntityModel;
+    use sea_orm::entity::prelude::*;
+
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "source_file")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        #[sea_orm(unique)]
+        path: String,
     }
 
     impl ActiveModelBehavior for ActiveModel {}
@@ -130,36 +346,166 @@ pub mod proc_decl {
         #[sea_orm(primary_key)]
         pub id: i32,
         path: String,
+        /// The type the proc is on, for joining against `type_closure`.
+        pub type_decl_id: i32,
+        #[sea_orm(belongs_to, from = "type_decl_id", to = "id")]
+        pub type_decl: Option<super::type_decl::Entity>,
+        pub name: String,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod proc_signature {
+    use sea_orm::DeriveEntityModel;
+    use sea_orm::entity::prelude::*;
+
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "proc_signature")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        /// The parameter list as written in DM, without the parentheses.
+        #[sea_orm(column_type = "Text")]
+        signature: String,
+        /// SHA-256 of `signature`, which is too long to index.
+        #[sea_orm(unique, column_type = "String(StringLen::N(64))")]
+        signature_hash: String,
+        #[sea_orm(has_many)]
+        pub params: HasMany<super::proc_param::Entity>,
     }
 
     impl ActiveModelBehavior for ActiveModel {}
 }
 
-pub mod proc_decl_snapshot {
+pub mod proc_param {
     use sea_orm::DeriveEntityModel;
     use sea_orm::entity::prelude::*;
 
     #[sea_orm::model]
     #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
-    #[sea_orm(table_name = "proc_decl_snapshot")]
+    #[sea_orm(table_name = "proc_param")]
     pub struct Model {
         #[sea_orm(primary_key, auto_increment = false)]
-        pub snapshot_id: i32,
+        pub proc_signature_id: i32,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub position: i32,
+        #[sea_orm(belongs_to, from = "proc_signature_id", to = "id")]
+        proc_signature: HasOne<super::proc_signature::Entity>,
+        name: String,
+        declared_type: Option<String>,
+        #[sea_orm(column_type = "Text", nullable)]
+        default: Option<String>,
+        input_type: Option<String>,
+        #[sea_orm(column_type = "Text", nullable)]
+        in_list: Option<String>,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod proc_decl_validity {
+    use sea_orm::entity::prelude::*;
+    use sea_orm::{Condition, DeriveEntityModel};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(
+        Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+    )]
+    #[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+    pub enum ProcKind {
+        #[sea_orm(string_value = "proc")]
+        Proc,
+        #[sea_orm(string_value = "verb")]
+        Verb,
+    }
+
+    /// How a proc definition calls its parent with `..()`.
+    #[derive(
+        Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+    )]
+    #[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+    pub enum ParentCall {
+        /// The definition never calls `..()`.
+        #[sea_orm(string_value = "none")]
+        None,
+        /// `..()` is only called on some paths through the body, e.g. in a
+        /// branch or loop, after `&&` or after an early `return`.
+        #[sea_orm(string_value = "conditional")]
+        Conditional,
+        /// `..()` is called every time the body runs.
+        #[sea_orm(string_value = "unconditional")]
+        Unconditional,
+    }
+
+    /// A proc that existed with the same attributes in every `ok` snapshot of
+    /// a codebase from `first_seq` to `last_seq`.
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "proc_decl_validity")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub codebase_id: i32,
         #[sea_orm(primary_key, auto_increment = false)]
         pub proc_decl_id: i32,
-        #[sea_orm(belongs_to, from = "snapshot_id", to = "id")]
-        pub snapshot: Option<super::snapshot::Entity>,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub first_seq: i32,
+        pub last_seq: i32,
+        #[sea_orm(belongs_to, from = "codebase_id", to = "id")]
+        pub codebase: Option<super::codebase::Entity>,
         #[sea_orm(belongs_to, from = "proc_decl_id", to = "id")]
         pub proc_decl: Option<super::proc_decl::Entity>,
+        pub proc_signature_id: i32,
+        #[sea_orm(belongs_to, from = "proc_signature_id", to = "id")]
+        pub proc_signature: Option<super::proc_signature::Entity>,
+        pub kind: ProcKind,
+        /// Whether the type overrides a proc declared on an ancestor.
+        pub is_override: bool,
+        /// How many times the proc is defined on the type.
+        pub definition_count: i32,
+        pub is_static: bool,
+        pub is_final: bool,
+        /// `set SpacemanDMM_*` settings in the effective definition, one
+        /// `name = value` per line.
+        #[sea_orm(column_type = "Text", nullable)]
+        pub settings: Option<String>,
+        /// Whether the effective definition calls `..()`.
+        pub parent_call: ParentCall,
+        /// Whether an ancestor's definition is marked `SHOULD_CALL_PARENT`.
+        pub should_call_parent: bool,
+        pub source_file_id: Option<i32>,
+        #[sea_orm(belongs_to, from = "source_file_id", to = "id")]
+        pub source_file: Option<super::source_file::Entity>,
+        pub line: Option<i32>,
+        pub column: Option<i32>,
     }
 
     impl ActiveModelBehavior for ActiveModel {}
+
+    impl super::Validity for Entity {
+        type Key = i32;
+
+        const CODEBASE_ID: Column = Column::CodebaseId;
+        const FIRST_SEQ: Column = Column::FirstSeq;
+        const LAST_SEQ: Column = Column::LastSeq;
+
+        fn key(model: &Model) -> i32 {
+            model.proc_decl_id
+        }
+
+        fn key_condition(keys: &[i32]) -> Condition {
+            Condition::all().add(Column::ProcDeclId.is_in(keys.iter().copied()))
+        }
+    }
 }
 
 pub mod var_decl {
     use sea_orm::DeriveEntityModel;
     use sea_orm::entity::prelude::*;
 
+    /// A var on a type, e.g. `/obj/item/gun/fire_delay`. The values it takes
+    /// over time are in `var_value`.
     #[sea_orm::model]
     #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
     #[sea_orm(table_name = "var_decl")]
@@ -167,42 +513,247 @@ pub mod var_decl {
         #[sea_orm(primary_key)]
         pub id: i32,
         path: String,
+        #[sea_orm(has_many)]
+        pub values: HasMany<super::var_value::Entity>,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod var_value {
+    use sea_orm::DeriveEntityModel;
+    use sea_orm::entity::prelude::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(
+        Clone, Copy, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+    )]
+    #[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+    pub enum VarValueKind {
+        /// The value was constant-evaluated into `json_const_val`.
+        #[sea_orm(string_value = "constant")]
+        Constant,
+        /// The value couldn't be constant-evaluated; its source is in
+        /// `expression`.
+        #[sea_orm(string_value = "expression")]
+        Expression,
+        /// The var has no initializer.
+        #[sea_orm(string_value = "none")]
+        None,
+    }
+
+    /// One distinct declaration and value of a var.
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "var_value")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub var_decl_id: i32,
+        #[sea_orm(belongs_to, from = "var_decl_id", to = "id")]
+        var_decl: HasOne<super::var_decl::Entity>,
         declared_type_id: Option<i32>,
         #[sea_orm(belongs_to, from = "declared_type_id", to = "id")]
         declared_type: HasOne<super::type_decl::Entity>,
+        is_const: bool,
+        /// Also set for `global` vars.
+        is_static: bool,
+        is_tmp: bool,
+        is_final: bool,
+        value_kind: VarValueKind,
         #[sea_orm(column_type = "Text")]
         json_const_val: String,
+        #[sea_orm(column_type = "Text", nullable)]
+        expression: Option<String>,
+        /// SHA-256 of all of the above, which together are too long to index.
+        #[sea_orm(unique, column_type = "String(StringLen::N(64))")]
+        value_hash: String,
     }
 
     impl ActiveModelBehavior for ActiveModel {}
 }
 
-pub mod var_decl_snapshot {
-    use sea_orm::DeriveEntityModel;
+pub mod var_decl_validity {
     use sea_orm::entity::prelude::*;
+    use sea_orm::{Condition, DeriveEntityModel};
 
+    /// A var that existed with the same value and location in every `ok`
+    /// snapshot of a codebase from `first_seq` to `last_seq`.
     #[sea_orm::model]
     #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
-    #[sea_orm(table_name = "var_decl_snapshot")]
+    #[sea_orm(table_name = "var_decl_validity")]
     pub struct Model {
         #[sea_orm(primary_key, auto_increment = false)]
-        pub snapshot_id: i32,
+        pub codebase_id: i32,
         #[sea_orm(primary_key, auto_increment = false)]
         pub var_decl_id: i32,
-        #[sea_orm(belongs_to, from = "snapshot_id", to = "id")]
-        pub snapshot: Option<super::snapshot::Entity>,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub first_seq: i32,
+        pub last_seq: i32,
+        #[sea_orm(belongs_to, from = "codebase_id", to = "id")]
+        pub codebase: Option<super::codebase::Entity>,
         #[sea_orm(belongs_to, from = "var_decl_id", to = "id")]
         pub var_decl: Option<super::var_decl::Entity>,
+        pub var_value_id: i32,
+        #[sea_orm(belongs_to, from = "var_value_id", to = "id")]
+        pub var_value: Option<super::var_value::Entity>,
+        pub source_file_id: Option<i32>,
+        #[sea_orm(belongs_to, from = "source_file_id", to = "id")]
+        pub source_file: Option<super::source_file::Entity>,
+        pub line: Option<i32>,
+        pub column: Option<i32>,
     }
 
     impl ActiveModelBehavior for ActiveModel {}
+
+    impl super::Validity for Entity {
+        type Key = i32;
+
+        const CODEBASE_ID: Column = Column::CodebaseId;
+        const FIRST_SEQ: Column = Column::FirstSeq;
+        const LAST_SEQ: Column = Column::LastSeq;
+
+        fn key(model: &Model) -> i32 {
+            model.var_decl_id
+        }
+
+        fn key_condition(keys: &[i32]) -> Condition {
+            Condition::all().add(Column::VarDeclId.is_in(keys.iter().copied()))
+        }
+    }
 }
 
-pub async fn log_entry_from_commit(
-    txn: &DatabaseTransaction,
+pub mod var_effective_validity {
+    use sea_orm::entity::prelude::*;
+    use sea_orm::{Condition, DeriveEntityModel};
+
+    /// The value a var effectively has on a type in every `ok` snapshot of a
+    /// codebase from `first_seq` to `last_seq`, whether it is set on the type
+    /// itself or inherited from the nearest ancestor that sets it. Only
+    /// recorded for the var names in a codebase's `inherited_vars`.
+    #[sea_orm::model]
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "var_effective_validity")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub codebase_id: i32,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub type_decl_id: i32,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub name: String,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub first_seq: i32,
+        pub last_seq: i32,
+        #[sea_orm(belongs_to, from = "codebase_id", to = "id")]
+        pub codebase: Option<super::codebase::Entity>,
+        #[sea_orm(belongs_to, from = "type_decl_id", to = "id")]
+        pub type_decl: Option<super::type_decl::Entity>,
+        /// The var on the type the value comes from.
+        pub var_decl_id: i32,
+        #[sea_orm(belongs_to, from = "var_decl_id", to = "id")]
+        pub var_decl: Option<super::var_decl::Entity>,
+        pub var_value_id: i32,
+        #[sea_orm(belongs_to, from = "var_value_id", to = "id")]
+        pub var_value: Option<super::var_value::Entity>,
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+
+    impl super::Validity for Entity {
+        type Key = (i32, String);
+
+        const CODEBASE_ID: Column = Column::CodebaseId;
+        const FIRST_SEQ: Column = Column::FirstSeq;
+        const LAST_SEQ: Column = Column::LastSeq;
+
+        fn key(model: &Model) -> (i32, String) {
+            (model.type_decl_id, model.name.clone())
+        }
+
+        fn key_condition(keys: &[(i32, String)]) -> Condition {
+            keys.iter()
+                .fold(Condition::any(), |condition, (type_decl_id, name)| {
+                    condition.add(
+                        Condition::all()
+                            .add(Column::TypeDeclId.eq(*type_decl_id))
+                            .add(Column::Name.eq(name.as_str())),
+                    )
+                })
+        }
+    }
+}
+
+/// A table of validity intervals, keyed by codebase, the key of a row within
+/// a snapshot and the `seq` the interval starts at.
+pub trait Validity: EntityTrait {
+    /// Identifies a row within a snapshot, e.g. its decl.
+    type Key: Clone + Eq + Hash + Send + Sync;
+
+    const CODEBASE_ID: Self::Column;
+    const FIRST_SEQ: Self::Column;
+    const LAST_SEQ: Self::Column;
+
+    fn key(model: &Self::Model) -> Self::Key;
+
+    /// Matches the rows with any of `keys`.
+    fn key_condition(keys: &[Self::Key]) -> Condition;
+}
+
+pub async fn find_or_create_codebase(
+    db: &DatabaseConnection,
+    name: &str,
+) -> Result<i32, IngesterError> {
+    let model = codebase::Entity::find()
+        .filter(codebase::Column::Name.eq(name))
+        .one(db)
+        .await?;
+    if let Some(codebase) = model {
+        return Ok(codebase.id);
+    }
+
+    let codebase = codebase::ActiveModel {
+        name: Set(name.to_owned()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(codebase.id)
+}
+
+/// A commit's log entry and numstats, read out of the repository ahead of
+/// being written so the writer never has to touch git.
+#[derive(Clone)]
+pub struct CommitLog {
+    entry: git_log_entry::ActiveModel,
+    numstats: Vec<git_commit_log_numstat_entry::ActiveModel>,
+    /// Whether the commit changes any DM sources compared to its first parent.
+    pub sources_changed: bool,
+}
+
+/// Diffs `commit` against its first parent.
+pub fn first_parent_diff<'repo>(
+    repo: &'repo git2::Repository,
+    commit: &git2::Commit<'_>,
+) -> Result<git2::Diff<'repo>, IngesterError> {
+    // Root commits are diffed against the empty tree.
+    let parent_tree = commit.parents().next().map(|p| p.tree()).transpose()?;
+    Ok(repo.diff_tree_to_tree(Some(&commit.tree()?), parent_tree.as_ref(), None)?)
+}
+
+pub fn commit_log(
     repo: &git2::Repository,
     commit: &git2::Commit<'_>,
-) -> Result<i32, IngesterError> {
+) -> Result<CommitLog, IngesterError> {
+    commit_log_from_diff(commit, &first_parent_diff(repo, commit)?)
+}
+
+/// Like [`commit_log`], for when the diff against the first parent is already
+/// at hand.
+pub fn commit_log_from_diff(
+    commit: &git2::Commit<'_>,
+    diff: &git2::Diff<'_>,
+) -> Result<CommitLog, IngesterError> {
     let msg = commit.message().unwrap();
     let (subject, body) = if msg.contains('\n') {
         msg.split_once('\n').unwrap()
@@ -210,7 +761,7 @@ pub async fn log_entry_from_commit(
         (msg, "")
     };
 
-    let model = git_log_entry::ActiveModel {
+    let entry = git_log_entry::ActiveModel {
         commit_hash: Set(commit.id().to_string()),
         tree_hash: Set(commit.tree_id().to_string()),
         parent_hashes: Set(commit
@@ -238,19 +789,13 @@ pub async fn log_entry_from_commit(
         ..Default::default()
     };
 
-    let entry = git_log_entry::Entity::insert(model).exec(txn).await?;
-
-    let mut numstat_entries = vec![];
-    let diff = repo.diff_tree_to_tree(
-        Some(commit.tree().as_ref().unwrap()),
-        Some(commit.parent(0).as_ref().unwrap().tree().as_ref().unwrap()),
-        None,
-    )?;
+    let mut numstats = vec![];
+    let sources_changed = touches_sources(diff);
 
     let diffstats = diff.stats()?;
     let buf = diffstats.to_buf(DiffStatsFormat::NUMBER, 9999)?;
-    let numstats = buf.as_str().unwrap();
-    for numstat_line in numstats.split("\n") {
+    let numstat_lines = buf.as_str().unwrap();
+    for numstat_line in numstat_lines.split("\n") {
         let mut add = -1;
         let mut sub = -1;
 
@@ -266,18 +811,65 @@ pub async fn log_entry_from_commit(
         }
 
         let numstat_entry = git_commit_log_numstat_entry::ActiveModel {
-            git_log_entry_id: Set(entry.last_insert_id),
             add: Set(add),
             sub: Set(sub),
             path_state: Set(splits[2].to_owned()),
             ..Default::default()
         };
-        numstat_entries.push(numstat_entry.to_owned());
+        numstats.push(numstat_entry);
     }
 
-    git_commit_log_numstat_entry::Entity::insert_many(numstat_entries)
-        .exec(txn)
-        .await?;
+    Ok(CommitLog {
+        entry,
+        numstats,
+        sources_changed,
+    })
+}
+
+/// Finds the snapshot of the commit `commit_hash` in a codebase.
+pub async fn find_snapshot(
+    txn: &DatabaseTransaction,
+    codebase_id: i32,
+    commit_hash: &str,
+) -> Result<Option<snapshot::Model>, IngesterError> {
+    let Some(entry) = git_log_entry::Entity::find()
+        .filter(git_log_entry::Column::CodebaseId.eq(codebase_id))
+        .filter(git_log_entry::Column::CommitHash.eq(commit_hash))
+        .one(txn)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    Ok(snapshot::Entity::find()
+        .filter(snapshot::Column::GitLogEntryId.eq(entry.id))
+        .one(txn)
+        .await?)
+}
+
+pub async fn insert_commit_log(
+    txn: &DatabaseTransaction,
+    codebase_id: i32,
+    log: CommitLog,
+) -> Result<i32, IngesterError> {
+    let mut entry = log.entry;
+    entry.codebase_id = Set(codebase_id);
+    let entry = git_log_entry::Entity::insert(entry).exec(txn).await?;
+
+    let numstat_entries: Vec<_> = log
+        .numstats
+        .into_iter()
+        .map(|mut numstat_entry| {
+            numstat_entry.git_log_entry_id = Set(entry.last_insert_id);
+            numstat_entry
+        })
+        .collect();
+
+    if !numstat_entries.is_empty() {
+        git_commit_log_numstat_entry::Entity::insert_many(numstat_entries)
+            .exec(txn)
+            .await?;
+    }
 
     Ok(entry.last_insert_id)
 }
@@ -0,0 +1,107 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use git2::Oid;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{
+    IngesterError,
+    cache::content_hash,
+    extract::{ParsedDiagnostic, ParsedTree},
+};
+
+/// Version of what a [`CachedParse`] holds. Bump it whenever that changes:
+/// the fields stored, what extraction records in them, or how constants are
+/// encoded. Entries are stored under it and the parser's version, so entries
+/// written by other versions are ignored rather than misread.
+const FORMAT_VERSION: u32 = 1;
+
+/// The version and source, including the resolved git revision, that
+/// `Cargo.lock` pins the parser to.
+fn parser_version() -> String {
+    include_str!("../Cargo.lock")
+        .split("[[package]]")
+        .find(|package| package.lines().any(|line| line == r#"name = "dreammaker""#))
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| line.starts_with("version = ") || line.starts_with("source = "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// What parsing an environment produced.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CachedParse {
+    /// The extracted decls, or why the environment failed to parse.
+    pub result: Result<ParsedTree, String>,
+    pub diagnostics: Vec<ParsedDiagnostic>,
+}
+
+/// Parse results of one environment stored on disk by the hash of the git tree
+/// they were parsed from, so re-ingesting history (into this or another
+/// database) doesn't have to parse it again.
+#[derive(Clone)]
+pub(crate) struct ParseCache {
+    dir: PathBuf,
+}
+
+impl ParseCache {
+    pub(crate) fn new(root: &Path, dme: &str) -> Result<Self, IngesterError> {
+        let format = content_hash([
+            Some(FORMAT_VERSION.to_string().as_str()),
+            Some(parser_version().as_str()),
+        ]);
+        let dir = root.join(&format[..16]).join(dme);
+        std::fs::create_dir_all(&dir)?;
+        Ok(ParseCache { dir })
+    }
+
+    fn path(&self, tree: Oid) -> PathBuf {
+        self.dir.join(format!("{}.json.gz", tree))
+    }
+
+    /// The cached parse of `tree`, if there is one.
+    pub(crate) fn get(&self, tree: Oid) -> Result<Option<CachedParse>, IngesterError> {
+        let file = match File::open(self.path(tree)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let reader = BufReader::new(GzDecoder::new(file));
+        serde_json::from_reader(reader)
+            .map(Some)
+            .map_err(|e| IngesterError::Cache(format!("unreadable parse of {}: {}", tree, e)))
+    }
+
+    pub(crate) fn put(&self, tree: Oid, parse: &CachedParse) -> Result<(), IngesterError> {
+        // Written to a uniquely named temporary file first so other workers,
+        // concurrent runs and interrupted runs never see half an entry.
+        let temp = NamedTempFile::new_in(&self.dir)?;
+        let mut writer = BufWriter::new(GzEncoder::new(temp, Compression::fast()));
+        serde_json::to_writer(&mut writer, parse)
+            .map_err(|e| IngesterError::Cache(format!("unwritable parse of {}: {}", tree, e)))?;
+        let mut temp = writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+        temp.flush()?;
+        temp.persist(self.path(tree)).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parser_version_has_the_resolved_revision() {
+        let version = parser_version();
+        assert!(version.contains("version = "));
+        assert!(version.contains("SpacemanDMM.git"));
+        assert!(version.contains('#'));
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use chrono::{Datelike, NaiveDate};
+use git2::{Commit, Diff, Pathspec, PathspecFlags};
+
+use crate::{Args, IngesterError};
+
+/// How often `--every` samples commits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Every {
+    Day,
+    Week,
+    /// Every given number of commits.
+    Commits(usize),
+}
+
+impl FromStr for Every {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Every::Day),
+            "week" => Ok(Every::Week),
+            _ => match s.parse() {
+                Ok(0) | Err(_) => Err(format!(
+                    "expected `day`, `week` or a number of commits, got `{}`",
+                    s
+                )),
+                Ok(n) => Ok(Every::Commits(n)),
+            },
+        }
+    }
+}
+
+pub(crate) fn commit_date(commit: &Commit) -> NaiveDate {
+    chrono::DateTime::from_timestamp(commit.time().seconds(), 0)
+        .unwrap()
+        .date_naive()
+}
+
+/// Whether `commit` passes the `--since` and `--until` filters.
+pub(crate) fn in_date_range(commit: &Commit, args: &Args) -> bool {
+    let date = commit_date(commit);
+    !(args.since.is_some_and(|since| date < since) || args.until.is_some_and(|until| date > until))
+}
+
+/// The `--path` filter, if any paths were given.
+pub(crate) fn pathspec(args: &Args) -> Result<Option<Pathspec>, IngesterError> {
+    if args.path.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Pathspec::new(args.path.iter())?))
+}
+
+/// Whether `diff` changes a path matching `pathspec`.
+pub(crate) fn touches(pathspec: &Pathspec, diff: &Diff) -> Result<bool, IngesterError> {
+    Ok(pathspec
+        .match_diff(diff, PathspecFlags::DEFAULT)?
+        .diff_entries()
+        .next()
+        .is_some())
+}
+
+/// Keeps the last of `items` in each period of `every`, e.g. the last commit
+/// of each day. `dates` are the commit dates of `items`, which are in history
+/// order, and `ingested` whether each is already in the database. Ingested
+/// items are always kept, and a period that has one is already sampled, so
+/// nothing else in it is.
+pub(crate) fn sample<T>(
+    items: Vec<T>,
+    dates: &[NaiveDate],
+    ingested: &[bool],
+    every: Every,
+) -> Vec<T> {
+    let periods: Vec<i64> = dates
+        .iter()
+        .enumerate()
+        .map(|(index, date)| match every {
+            Every::Day => date.num_days_from_ce() as i64,
+            Every::Week => {
+                let week = date.iso_week();
+                week.year() as i64 * 100 + week.week() as i64
+            }
+            Every::Commits(n) => (index / n) as i64,
+        })
+        .collect();
+    // Commit dates aren't monotonic, so a period may come up again after a
+    // later one; only its last commit counts.
+    let last: HashMap<i64, usize> = periods
+        .iter()
+        .enumerate()
+        .map(|(index, &period)| (period, index))
+        .collect();
+    let sampled: HashSet<i64> = periods
+        .iter()
+        .zip(ingested)
+        .filter(|(_, ingested)| **ingested)
+        .map(|(&period, _)| period)
+        .collect();
+
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| {
+            let period = periods[*index];
+            ingested[*index] || (!sampled.contains(&period) && last[&period] == *index)
+        })
+        .map(|(_, item)| item)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_every() {
+        assert_eq!("day".parse(), Ok(Every::Day));
+        assert_eq!("week".parse(), Ok(Every::Week));
+        assert_eq!("100".parse(), Ok(Every::Commits(100)));
+        assert!("0".parse::<Every>().is_err());
+        assert!("month".parse::<Every>().is_err());
+        assert!("-1".parse::<Every>().is_err());
+    }
+
+    #[test]
+    fn samples_the_last_commit_of_each_day() {
+        let dates = [
+            date("2024-01-01"),
+            date("2024-01-01"),
+            date("2024-01-02"),
+            // Committed out of order, e.g. a rebased commit.
+            date("2024-01-01"),
+            date("2024-01-03"),
+        ];
+        assert_eq!(
+            sample(
+                vec!['a', 'b', 'c', 'd', 'e'],
+                &dates,
+                &[false; 5],
+                Every::Day
+            ),
+            vec!['c', 'd', 'e']
+        );
+    }
+
+    #[test]
+    fn samples_the_last_commit_of_each_week() {
+        // 2024-01-01 is a Monday, and 2023-12-31 the Sunday before it.
+        let dates = [
+            date("2023-12-31"),
+            date("2024-01-01"),
+            date("2024-01-07"),
+            date("2024-01-08"),
+        ];
+        assert_eq!(
+            sample(vec!['a', 'b', 'c', 'd'], &dates, &[false; 4], Every::Week),
+            vec!['a', 'c', 'd']
+        );
+    }
+
+    #[test]
+    fn samples_the_last_of_each_run_of_commits() {
+        let dates = [date("2024-01-01"); 5];
+        assert_eq!(
+            sample(vec![1, 2, 3, 4, 5], &dates, &[false; 5], Every::Commits(2)),
+            vec![2, 4, 5]
+        );
+    }
+
+    #[test]
+    fn skips_periods_with_an_ingested_commit() {
+        let dates = [
+            date("2024-01-01"),
+            date("2024-01-01"),
+            date("2024-01-02"),
+            date("2024-01-02"),
+        ];
+        // 'a' was the last commit of its day when it was ingested.
+        assert_eq!(
+            sample(
+                vec!['a', 'b', 'c', 'd'],
+                &dates,
+                &[true, false, false, false],
+                Every::Day
+            ),
+            vec!['a', 'd']
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use git2::{Delta, Diff, ObjectType, Oid, Repository, Tree, TreeWalkMode, TreeWalkResult};
+
+use crate::IngesterError;
+
+/// Extensions of files the preprocessor actually reads.
+const SOURCE_EXTENSIONS: &[&str] = &["dme", "dm", "dmf", "dms"];
+
+/// Extensions of files that may be `#include`d but whose contents the
+/// preprocessor never reads. These only need to exist, so they are written
+/// out empty instead of copying (potentially very large) map files.
+const PLACEHOLDER_EXTENSIONS: &[&str] = &["dmm"];
+
+/// A scratch directory holding the DM sources of a single commit, read
+/// straight out of the git object database.
+///
+/// The dreammaker preprocessor can only read from the filesystem, so rather
+/// than checking out the commit in the user's repository we mirror just the
+/// files it needs into a directory we own. This works against bare
+/// repositories and never touches a working copy. Only files that changed
+/// since the previous checkout are rewritten.
+pub(crate) struct TreeSource {
+    root: PathBuf,
+    written: HashMap<PathBuf, Oid>,
+}
+
+impl TreeSource {
+    /// Starts from an empty `root`. Anything a previous run left there is
+    /// removed, so a missing `#include` can't silently pick up a stale copy.
+    pub(crate) fn new(root: PathBuf) -> Result<Self, IngesterError> {
+        match std::fs::remove_dir_all(&root) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        std::fs::create_dir_all(&root)?;
+        Ok(TreeSource {
+            root,
+            written: Default::default(),
+        })
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub(crate) fn checkout(&mut self, repo: &Repository, tree: &Tree) -> Result<(), IngesterError> {
+        let mut entries: HashMap<PathBuf, Oid> = HashMap::new();
+        tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() == Some(ObjectType::Blob)
+                && let Some(name) = entry.name()
+                && let Some(ext) = extension(name)
+                && (SOURCE_EXTENSIONS.contains(&ext.as_str())
+                    || PLACEHOLDER_EXTENSIONS.contains(&ext.as_str()))
+            {
+                entries.insert(Path::new(dir).join(name), entry.id());
+            }
+            TreeWalkResult::Ok
+        })?;
+
+        // Only remove files we wrote ourselves, never anything else that
+        // happens to live in the scratch directory.
+        let stale: Vec<PathBuf> = self
+            .written
+            .keys()
+            .filter(|path| !entries.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in stale {
+            match std::fs::remove_file(self.root.join(&path)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            self.written.remove(&path);
+        }
+
+        for (path, oid) in entries {
+            if self.written.get(&path) == Some(&oid) {
+                continue;
+            }
+            let dest = self.root.join(&path);
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let placeholder = extension(&path.to_string_lossy())
+                .is_some_and(|ext| PLACEHOLDER_EXTENSIONS.contains(&ext.as_str()));
+            if placeholder {
+                std::fs::write(&dest, [])?;
+            } else {
+                let blob = repo.find_blob(oid)?;
+                std::fs::write(&dest, blob.content())?;
+            }
+            self.written.insert(path, oid);
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the DM sources checked out for either side of `diff` would differ.
+/// Placeholder files only matter when they are added or removed.
+pub(crate) fn touches_sources(diff: &Diff) -> bool {
+    diff.deltas().any(|delta| {
+        [delta.old_file(), delta.new_file()].iter().any(|file| {
+            let Some(ext) = file
+                .path()
+                .and_then(|path| path.to_str())
+                .and_then(extension)
+            else {
+                return false;
+            };
+            SOURCE_EXTENSIONS.contains(&ext.as_str())
+                || (PLACEHOLDER_EXTENSIONS.contains(&ext.as_str())
+                    && delta.status() != Delta::Modified)
+        })
+    })
+}
+
+fn extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+}
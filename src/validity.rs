@@ -0,0 +1,316 @@
+use std::{collections::HashMap, hash::Hash};
+
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DatabaseTransaction, EntityTrait,
+    IdenStatic, IntoActiveModel, Iterable, QueryFilter, QueryOrder, Value, sea_query::Expr,
+};
+
+use crate::{
+    IngesterError,
+    models::{
+        Validity, proc_decl_validity, snapshot, type_closure, type_decl_validity,
+        var_decl_validity, var_effective_validity,
+    },
+    writer::insert_chunked,
+};
+
+/// The attributes of each row in an open interval, keyed by the row's key.
+type OpenIntervals<K> = HashMap<K, Vec<Option<Value>>>;
+
+/// The rows of one snapshot, each with its key. The interval bounds of the
+/// rows are left unset.
+#[derive(Default)]
+pub(crate) struct SnapshotRows {
+    pub types: Vec<(i32, type_decl_validity::ActiveModel)>,
+    pub procs: Vec<(i32, proc_decl_validity::ActiveModel)>,
+    pub vars: Vec<(i32, var_decl_validity::ActiveModel)>,
+    pub closure: Vec<((i32, i32), type_closure::ActiveModel)>,
+    pub effective_vars: Vec<((i32, String), var_effective_validity::ActiveModel)>,
+}
+
+/// How many rows of a snapshot started a new interval and how many only
+/// extended an open one.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Written {
+    pub inserted: usize,
+    pub extended: usize,
+}
+
+impl std::ops::AddAssign for Written {
+    fn add_assign(&mut self, other: Self) {
+        self.inserted += other.inserted;
+        self.extended += other.extended;
+    }
+}
+
+/// The validity intervals of a codebase that are still open, i.e. the rows of
+/// its latest `ok` snapshot.
+pub(crate) struct Intervals {
+    codebase_id: i32,
+    /// `seq` of the latest `ok` snapshot, if there is one.
+    seq: Option<i32>,
+    types: OpenIntervals<i32>,
+    procs: OpenIntervals<i32>,
+    vars: OpenIntervals<i32>,
+    closure: OpenIntervals<(i32, i32)>,
+    effective_vars: OpenIntervals<(i32, String)>,
+}
+
+impl Intervals {
+    pub(crate) async fn load(
+        db: &DatabaseConnection,
+        codebase_id: i32,
+    ) -> Result<Self, IngesterError> {
+        let seq = snapshot::Entity::find()
+            .filter(snapshot::Column::CodebaseId.eq(codebase_id))
+            .filter(snapshot::Column::Seq.is_not_null())
+            .order_by_desc(snapshot::Column::Seq)
+            .one(db)
+            .await?
+            .and_then(|snapshot| snapshot.seq);
+
+        let mut intervals = Intervals {
+            codebase_id,
+            seq,
+            types: Default::default(),
+            procs: Default::default(),
+            vars: Default::default(),
+            closure: Default::default(),
+            effective_vars: Default::default(),
+        };
+        if let Some(seq) = seq {
+            intervals.types = load_open::<type_decl_validity::Entity>(db, codebase_id, seq).await?;
+            intervals.procs = load_open::<proc_decl_validity::Entity>(db, codebase_id, seq).await?;
+            intervals.vars = load_open::<var_decl_validity::Entity>(db, codebase_id, seq).await?;
+            intervals.closure = load_open::<type_closure::Entity>(db, codebase_id, seq).await?;
+            intervals.effective_vars =
+                load_open::<var_effective_validity::Entity>(db, codebase_id, seq).await?;
+        }
+
+        Ok(intervals)
+    }
+
+    pub(crate) fn codebase_id(&self) -> i32 {
+        self.codebase_id
+    }
+
+    /// The `seq` the next `ok` snapshot gets.
+    pub(crate) fn next_seq(&self) -> i32 {
+        self.seq.map_or(0, |seq| seq + 1)
+    }
+
+    /// Records `rows` as the rows of the snapshot at `next_seq()` and moves on
+    /// to it. Returns how many rows were inserted and extended.
+    pub(crate) async fn write(
+        &mut self,
+        txn: &DatabaseTransaction,
+        rows: SnapshotRows,
+        batch_size: usize,
+    ) -> Result<Written, IngesterError> {
+        let seq = self.next_seq();
+        let mut written = Written::default();
+        written += extend::<type_decl_validity::Entity>(
+            txn,
+            self.codebase_id,
+            self.seq,
+            seq,
+            &mut self.types,
+            rows.types,
+            batch_size,
+        )
+        .await?;
+        written += extend::<proc_decl_validity::Entity>(
+            txn,
+            self.codebase_id,
+            self.seq,
+            seq,
+            &mut self.procs,
+            rows.procs,
+            batch_size,
+        )
+        .await?;
+        written += extend::<var_decl_validity::Entity>(
+            txn,
+            self.codebase_id,
+            self.seq,
+            seq,
+            &mut self.vars,
+            rows.vars,
+            batch_size,
+        )
+        .await?;
+        written += extend::<type_closure::Entity>(
+            txn,
+            self.codebase_id,
+            self.seq,
+            seq,
+            &mut self.closure,
+            rows.closure,
+            batch_size,
+        )
+        .await?;
+        written += extend::<var_effective_validity::Entity>(
+            txn,
+            self.codebase_id,
+            self.seq,
+            seq,
+            &mut self.effective_vars,
+            rows.effective_vars,
+            batch_size,
+        )
+        .await?;
+        self.seq = Some(seq);
+
+        Ok(written)
+    }
+}
+
+async fn load_open<E>(
+    db: &DatabaseConnection,
+    codebase_id: i32,
+    seq: i32,
+) -> Result<OpenIntervals<E::Key>, IngesterError>
+where
+    E: Validity,
+    E::Model: IntoActiveModel<E::ActiveModel>,
+    E::ActiveModel: ActiveModelTrait<Entity = E>,
+{
+    Ok(E::find()
+        .filter(E::CODEBASE_ID.eq(codebase_id))
+        .filter(E::LAST_SEQ.eq(seq))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|model| (E::key(&model), attributes::<E>(&model.into_active_model())))
+        .collect())
+}
+
+/// Folds one snapshot's `rows` into the intervals in `open`. Rows whose
+/// attributes are unchanged since `previous_seq` have their interval extended
+/// to `seq`; the rest start a new one.
+async fn extend<E>(
+    txn: &DatabaseTransaction,
+    codebase_id: i32,
+    previous_seq: Option<i32>,
+    seq: i32,
+    open: &mut OpenIntervals<E::Key>,
+    rows: Vec<(E::Key, E::ActiveModel)>,
+    batch_size: usize,
+) -> Result<Written, IngesterError>
+where
+    E: Validity,
+    E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+{
+    let (extended, mut started) = fold(
+        open,
+        rows.into_iter().map(|(key, row)| {
+            let attributes = attributes::<E>(&row);
+            (key, attributes, row)
+        }),
+    );
+    for row in started.iter_mut() {
+        row.set(E::FIRST_SEQ, seq.into());
+        row.set(E::LAST_SEQ, seq.into());
+    }
+
+    if let Some(previous_seq) = previous_seq {
+        for chunk in extended.chunks(batch_size) {
+            E::update_many()
+                .col_expr(E::LAST_SEQ, Expr::value(seq))
+                .filter(E::CODEBASE_ID.eq(codebase_id))
+                .filter(E::LAST_SEQ.eq(previous_seq))
+                .filter(E::key_condition(chunk))
+                .exec(txn)
+                .await?;
+        }
+    }
+    let written = Written {
+        inserted: started.len(),
+        extended: extended.len(),
+    };
+    insert_chunked::<E>(txn, started, batch_size).await?;
+
+    Ok(written)
+}
+
+/// Replaces the open intervals in `open` with those of one snapshot's `rows`,
+/// given as `(key, attributes, row)`. Returns the keys whose attributes are
+/// unchanged, whose interval goes on, and the rows of the rest, which start a
+/// new one.
+fn fold<K, A, R>(
+    open: &mut HashMap<K, A>,
+    rows: impl IntoIterator<Item = (K, A, R)>,
+) -> (Vec<K>, Vec<R>)
+where
+    K: Clone + Eq + Hash,
+    A: PartialEq,
+{
+    let mut extended = vec![];
+    let mut started = vec![];
+    let mut next_open = HashMap::new();
+    for (key, attributes, row) in rows {
+        if open.get(&key) == Some(&attributes) {
+            extended.push(key.clone());
+        } else {
+            started.push(row);
+        }
+        next_open.insert(key, attributes);
+    }
+    *open = next_open;
+
+    (extended, started)
+}
+
+/// The values of `row` other than its interval bounds.
+fn attributes<E>(row: &E::ActiveModel) -> Vec<Option<Value>>
+where
+    E: Validity,
+    E::ActiveModel: ActiveModelTrait<Entity = E>,
+{
+    E::Column::iter()
+        .filter(|column| {
+            column.as_str() != E::FIRST_SEQ.as_str() && column.as_str() != E::LAST_SEQ.as_str()
+        })
+        .map(|column| row.get(column).into_value())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged_rows_extend_their_interval() {
+        let mut open = HashMap::from([(1, "a"), (2, "b"), (3, "c")]);
+        let (extended, started) = fold(
+            &mut open,
+            [
+                (1, "a", "row 1"),
+                (2, "changed", "row 2"),
+                (4, "d", "row 4"),
+            ],
+        );
+        assert_eq!(extended, vec![1]);
+        assert_eq!(started, vec!["row 2", "row 4"]);
+        // 3 is gone, so its interval ends at the previous snapshot.
+        assert_eq!(open, HashMap::from([(1, "a"), (2, "changed"), (4, "d")]));
+    }
+
+    #[test]
+    fn first_snapshot_starts_every_interval() {
+        let mut open = HashMap::new();
+        let (extended, started) = fold(&mut open, [((1, 2), 0, "row"), ((2, 2), 1, "row")]);
+        assert!(extended.is_empty());
+        assert_eq!(started.len(), 2);
+        assert_eq!(open.len(), 2);
+    }
+
+    #[test]
+    fn reappearing_rows_start_a_new_interval() {
+        let mut open = HashMap::from([(1, "a")]);
+        fold(&mut open, Vec::<(i32, &str, ())>::new());
+        let (extended, started) = fold(&mut open, [(1, "a", ())]);
+        assert!(extended.is_empty());
+        assert_eq!(started.len(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use git2::{Oid, Repository};
+use slog::{Logger, info, warn};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc::Sender};
+
+use crate::{
+    IngesterError,
+    dme::get_object_tree,
+    extract::{ParsedDiagnostic, ParsedTree, extract, extract_diagnostics},
+    models::{CommitLog, commit_log},
+    parse_cache::{CachedParse, ParseCache},
+    source::TreeSource,
+};
+
+/// What became of a commit's environment.
+pub(crate) enum ParseOutcome {
+    Parsed(ParsedTree),
+    /// The environment failed to parse, with the reason.
+    Failed(String),
+    /// There was nothing to parse, with the reason.
+    Skipped(String),
+    /// The DM sources are identical to those of the given earlier commit, so
+    /// the commit shares its snapshot instead of being parsed.
+    Alias(Oid),
+}
+
+/// A commit to ingest.
+#[derive(Clone)]
+pub(crate) struct Job {
+    pub oid: Oid,
+    /// An earlier commit with the same tree, if there is one.
+    pub alias_of: Option<Oid>,
+    /// The first parent, if it is ingested or comes earlier in the order. The
+    /// commit shares its snapshot if it doesn't change any DM sources.
+    pub parent: Option<Oid>,
+    /// The commit's log, if selecting it already took the diff it is read
+    /// from.
+    pub log: Option<CommitLog>,
+}
+
+/// Everything the writer needs to ingest one commit.
+pub(crate) struct ParsedCommit {
+    pub log: CommitLog,
+    pub outcome: ParseOutcome,
+    pub diagnostics: Vec<ParsedDiagnostic>,
+}
+
+/// The outcome of parsing the commit at `index` in the ingestion order.
+pub(crate) struct Parsed {
+    pub index: usize,
+    pub oid: Oid,
+    pub result: Result<ParsedCommit, IngesterError>,
+    /// Held until the commit has been written. Workers need a permit before
+    /// claiming a commit, which bounds how far parsing can run ahead of the
+    /// writer.
+    pub permit: OwnedSemaphorePermit,
+}
+
+/// A parser with its own repository handle and scratch checkout, so any
+/// number of them can run side by side.
+pub(crate) struct Worker {
+    repo: Repository,
+    source: TreeSource,
+    dme: String,
+    parse_cache: Option<ParseCache>,
+    logger: Logger,
+}
+
+impl Worker {
+    pub(crate) fn new(
+        repo_root: &Path,
+        scratch_dir: PathBuf,
+        dme: String,
+        parse_cache: Option<ParseCache>,
+        logger: Logger,
+    ) -> Result<Self, IngesterError> {
+        Ok(Worker {
+            repo: Repository::open(repo_root)?,
+            source: TreeSource::new(scratch_dir)?,
+            dme,
+            parse_cache,
+            logger,
+        })
+    }
+
+    /// Parses commits from `commits` on a blocking thread until none are
+    /// left, claiming the next unparsed one from the shared `next` counter
+    /// each time.
+    pub(crate) async fn run(
+        mut self,
+        commits: Arc<Vec<Job>>,
+        next: Arc<AtomicUsize>,
+        window: Arc<Semaphore>,
+        tx: Sender<Parsed>,
+    ) {
+        loop {
+            let Ok(permit) = window.clone().acquire_owned().await else {
+                break;
+            };
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(job) = commits.get(index).cloned() else {
+                break;
+            };
+            let oid = job.oid;
+
+            let parsed = tokio::task::spawn_blocking(move || {
+                let result = self.parse(job);
+                (self, result)
+            })
+            .await;
+            // A panicking parser takes its worker down with it, but the writer
+            // still needs to hear about the commit it was working on.
+            let (worker, result) = match parsed {
+                Ok((worker, result)) => (Some(worker), result),
+                Err(e) => (None, Err(e.into())),
+            };
+
+            let parsed = Parsed {
+                index,
+                oid,
+                result,
+                permit,
+            };
+            if tx.send(parsed).await.is_err() {
+                // The writer has gone away, nothing left to do.
+                break;
+            }
+            match worker {
+                Some(worker) => self = worker,
+                None => break,
+            }
+        }
+    }
+
+    fn parse(&mut self, job: Job) -> Result<ParsedCommit, IngesterError> {
+        let oid = job.oid;
+        let commit = self.repo.find_commit(oid)?;
+        let log = match job.log {
+            Some(log) => log,
+            None => commit_log(&self.repo, &commit)?,
+        };
+
+        let alias_of = job.alias_of.or(job.parent.filter(|_| !log.sources_changed));
+        if let Some(alias_of) = alias_of {
+            return Ok(ParsedCommit {
+                log,
+                outcome: ParseOutcome::Alias(alias_of),
+                diagnostics: vec![],
+            });
+        }
+
+        let commit_tree = commit.tree()?;
+        if commit_tree.get_name(&self.dme).is_none() {
+            return Ok(ParsedCommit {
+                log,
+                outcome: ParseOutcome::Skipped(format!("{} not found", self.dme)),
+                diagnostics: vec![],
+            });
+        }
+
+        let tree_id = commit_tree.id();
+        if let Some(parse_cache) = &self.parse_cache {
+            match parse_cache.get(tree_id) {
+                Ok(Some(cached)) => {
+                    info!(self.logger, "using cached parse of {} @{}", self.dme, oid);
+                    return Ok(parsed_commit(log, cached));
+                }
+                Ok(None) => {}
+                Err(e) => warn!(self.logger, "ignoring parse cache @{}: {}", oid, e),
+            }
+        }
+
+        let dt = chrono::DateTime::from_timestamp(commit.time().seconds(), 0).unwrap();
+        info!(
+            self.logger,
+            "parsing {} @{}, {}",
+            self.dme,
+            oid,
+            dt.format("%Y-%m-%d %H:%M:%S")
+        );
+
+        self.source.checkout(&self.repo, &commit_tree)?;
+        let dme_path = self.source.root().join(&self.dme);
+        let ctx = dreammaker::Context::default();
+        // Some historical commits trip up the parser badly enough to panic;
+        // those are recorded as failures like any other parse error.
+        let root = self.source.root();
+        let parsed = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            get_object_tree(&ctx, dme_path).map(|tree| extract(&tree, &ctx, root))
+        }));
+        let (result, panicked) = match parsed {
+            Ok(Ok(tree)) => (Ok(tree), false),
+            Ok(Err(IngesterError::Parser(e))) => (Err(e), false),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => (Err("parser panicked".into()), true),
+        };
+        let cached = CachedParse {
+            result,
+            diagnostics: extract_diagnostics(&ctx, root),
+        };
+        // A panic is a parser bug rather than a result, so it is retried next
+        // time instead of being cached.
+        if !panicked
+            && let Some(parse_cache) = &self.parse_cache
+            && let Err(e) = parse_cache.put(tree_id, &cached)
+        {
+            warn!(self.logger, "could not cache parse @{}: {}", oid, e);
+        }
+
+        Ok(parsed_commit(log, cached))
+    }
+}
+
+fn parsed_commit(log: CommitLog, parse: CachedParse) -> ParsedCommit {
+    ParsedCommit {
+        log,
+        outcome: match parse.result {
+            Ok(tree) => ParseOutcome::Parsed(tree),
+            Err(e) => ParseOutcome::Failed(e),
+        },
+        diagnostics: parse.diagnostics,
+    }
+}
@@ -0,0 +1,500 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Instant,
+};
+
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, DatabaseConnection, DatabaseTransaction, EntityTrait,
+    TransactionTrait,
+};
+use slog::{Logger, info, warn};
+use tokio::sync::mpsc::Receiver;
+
+use crate::{
+    IngesterError,
+    cache::Cache,
+    extract::{ParsedLocation, ParsedTree},
+    models::{
+        diagnostic, find_snapshot, insert_commit_log, proc_decl_validity,
+        snapshot::{self, SnapshotStatus},
+        type_closure, type_decl_validity, var_decl_validity, var_effective_validity,
+    },
+    validity::{Intervals, SnapshotRows},
+    worker::{ParseOutcome, Parsed},
+};
+
+/// Settings for how parsed commits are written.
+pub(crate) struct WriteOptions {
+    /// Maximum number of rows per multi-row insert.
+    pub batch_size: usize,
+    /// Var names to record effective values of, see `var_effective_validity`.
+    pub inherited_vars: Vec<String>,
+}
+
+/// Drains parsed commits from `rx` into the database, one transaction per
+/// commit, and returns how many were written.
+///
+/// Workers finish out of order; results are held on to until every earlier
+/// commit has been written so snapshots land in revwalk order.
+pub(crate) async fn run(
+    db: DatabaseConnection,
+    mut cache: Cache,
+    codebase_id: i32,
+    mut rx: Receiver<Parsed>,
+    options: WriteOptions,
+    logger: Logger,
+) -> Result<usize, IngesterError> {
+    let mut intervals = Intervals::load(&db, codebase_id).await?;
+    let mut pending = BTreeMap::new();
+    let mut next_index = 0;
+    while let Some(parsed) = rx.recv().await {
+        pending.insert(parsed.index, parsed);
+        while let Some(parsed) = pending.remove(&next_index) {
+            next_index += 1;
+            let Parsed {
+                oid,
+                result,
+                permit,
+                ..
+            } = parsed;
+            let commit = result?;
+
+            let txn = db.begin().await?;
+            let log_entry_id = insert_commit_log(&txn, codebase_id, commit.log).await?;
+            let (status, error, seq, alias_of) = match &commit.outcome {
+                ParseOutcome::Parsed(_) => {
+                    (SnapshotStatus::Ok, None, Some(intervals.next_seq()), None)
+                }
+                ParseOutcome::Failed(e) => {
+                    warn!(logger, "failed to parse @{}: {}", oid, e);
+                    (SnapshotStatus::Failed, Some(e.clone()), None, None)
+                }
+                ParseOutcome::Skipped(reason) => {
+                    (SnapshotStatus::Skipped, Some(reason.clone()), None, None)
+                }
+                ParseOutcome::Alias(alias_oid) => {
+                    info!(logger, "@{} shares the snapshot of @{}", oid, alias_oid);
+                    let target = find_snapshot(&txn, codebase_id, &alias_oid.to_string())
+                        .await?
+                        .ok_or_else(|| {
+                            IngesterError::Worker(format!("no snapshot of {} to alias", alias_oid))
+                        })?;
+                    (
+                        target.status,
+                        target.error,
+                        target.seq,
+                        Some(target.alias_of.unwrap_or(target.id)),
+                    )
+                }
+            };
+            let snapshot = snapshot::ActiveModel {
+                codebase_id: Set(codebase_id),
+                git_log_entry_id: Set(log_entry_id),
+                status: Set(status),
+                error: Set(error),
+                seq: Set(seq),
+                alias_of: Set(alias_of),
+                ..Default::default()
+            };
+            let snapshot_id = snapshot::Entity::insert(snapshot)
+                .exec(&txn)
+                .await?
+                .last_insert_id;
+
+            let diagnostics = commit
+                .diagnostics
+                .iter()
+                .map(|d| diagnostic::ActiveModel {
+                    snapshot_id: Set(snapshot_id),
+                    severity: Set(d.severity),
+                    file: Set(d.file.clone()),
+                    line: Set(d.line as i32),
+                    column: Set(d.column as i32),
+                    message: Set(d.message.clone()),
+                    code: Set(d.code.clone()),
+                    ..Default::default()
+                })
+                .collect();
+            insert_chunked::<diagnostic::Entity>(&txn, diagnostics, options.batch_size).await?;
+
+            if let ParseOutcome::Parsed(tree) = &commit.outcome {
+                info!(logger, "writing snapshot @{}", oid);
+                write_snapshot(&txn, &mut cache, &mut intervals, tree, &options, &logger).await?;
+            }
+
+            info!(logger, "committing transaction");
+            txn.commit().await?;
+            drop(permit);
+        }
+    }
+
+    Ok(next_index)
+}
+
+/// Writes the decls, type closure and effective vars of `tree` as the next `ok`
+/// snapshot in `intervals`.
+async fn write_snapshot(
+    txn: &DatabaseTransaction,
+    cache: &mut Cache,
+    intervals: &mut Intervals,
+    tree: &ParsedTree,
+    options: &WriteOptions,
+    logger: &Logger,
+) -> Result<(), IngesterError> {
+    let batch_size = options.batch_size;
+    let codebase_id = intervals.codebase_id();
+    let started = Instant::now();
+
+    let mut rows = SnapshotRows::default();
+    let mut parents = HashMap::new();
+    let mut type_ids = HashMap::new();
+    let mut local_vars = HashMap::new();
+
+    let mut count = 0;
+    for type_ in tree.types.iter() {
+        let type_decl_id = cache.get_type(&type_.path, txn).await?.id;
+        let parent_type_id = match &type_.parent {
+            Some(parent) => Some(cache.get_type(parent, txn).await?.id),
+            None => None,
+        };
+        parents.insert(type_decl_id, parent_type_id);
+        type_ids.insert(type_.path.as_str(), type_decl_id);
+        let (source_file_id, line, column) =
+            location_columns(cache, type_.location.as_ref(), txn).await?;
+        rows.types.push((
+            type_decl_id,
+            type_decl_validity::ActiveModel {
+                codebase_id: Set(codebase_id),
+                type_decl_id: Set(type_decl_id),
+                parent_type_id: Set(parent_type_id),
+                explicit_parent: Set(type_.explicit_parent),
+                source_file_id: Set(source_file_id),
+                line: Set(line),
+                column: Set(column),
+                ..Default::default()
+            },
+        ));
+
+        for proc in type_.procs.iter() {
+            let proc_name = format!("{}/{}", type_.path, proc.name);
+            let proc_decl_id = cache
+                .get_proc(&proc_name, type_decl_id, &proc.name, txn)
+                .await?
+                .id;
+            let (source_file_id, line, column) =
+                location_columns(cache, proc.location.as_ref(), txn).await?;
+            let proc_signature_id = cache.get_proc_signature(proc, txn).await?.id;
+            rows.procs.push((
+                proc_decl_id,
+                proc_decl_validity::ActiveModel {
+                    codebase_id: Set(codebase_id),
+                    proc_decl_id: Set(proc_decl_id),
+                    proc_signature_id: Set(proc_signature_id),
+                    kind: Set(proc.kind),
+                    is_override: Set(proc.is_override),
+                    definition_count: Set(proc.definition_count as i32),
+                    is_static: Set(proc.is_static),
+                    is_final: Set(proc.is_final),
+                    settings: Set(if proc.settings.is_empty() {
+                        None
+                    } else {
+                        Some(proc.settings.join("\n"))
+                    }),
+                    parent_call: Set(proc.parent_call),
+                    should_call_parent: Set(proc.should_call_parent),
+                    source_file_id: Set(source_file_id),
+                    line: Set(line),
+                    column: Set(column),
+                    ..Default::default()
+                },
+            ));
+        }
+
+        for var in type_.vars.iter() {
+            let var_path = format!("{}/{}", type_.path, var.name);
+            let var_decl_id = cache.get_var_decl(&var_path, txn).await?.id;
+            let var_value_id = cache.get_var_value(var_decl_id, var, txn).await?.id;
+            let (source_file_id, line, column) =
+                location_columns(cache, var.location.as_ref(), txn).await?;
+            if options.inherited_vars.contains(&var.name) {
+                local_vars.insert(
+                    (type_.path.as_str(), var.name.as_str()),
+                    (var_decl_id, var_value_id),
+                );
+            }
+            rows.vars.push((
+                var_decl_id,
+                var_decl_validity::ActiveModel {
+                    codebase_id: Set(codebase_id),
+                    var_decl_id: Set(var_decl_id),
+                    var_value_id: Set(var_value_id),
+                    source_file_id: Set(source_file_id),
+                    line: Set(line),
+                    column: Set(column),
+                    ..Default::default()
+                },
+            ));
+        }
+        count += 1;
+        if count % 1000 == 0 {
+            info!(logger, "{} paths", count)
+        }
+    }
+
+    rows.closure = closure_rows(&parents)
+        .into_iter()
+        .map(|(ancestor, descendant, depth)| {
+            (
+                (ancestor, descendant),
+                type_closure::ActiveModel {
+                    codebase_id: Set(codebase_id),
+                    ancestor_type_decl_id: Set(ancestor),
+                    descendant_type_decl_id: Set(descendant),
+                    depth: Set(depth),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+    rows.effective_vars = resolve_inherited(tree, &options.inherited_vars, &type_ids, &local_vars)
+        .into_iter()
+        .map(|(type_decl_id, name, (var_decl_id, var_value_id))| {
+            (
+                (type_decl_id, name.clone()),
+                var_effective_validity::ActiveModel {
+                    codebase_id: Set(codebase_id),
+                    type_decl_id: Set(type_decl_id),
+                    name: Set(name.clone()),
+                    var_decl_id: Set(var_decl_id),
+                    var_value_id: Set(var_value_id),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect();
+    let written = intervals.write(txn, rows, batch_size).await?;
+
+    let elapsed = started.elapsed().as_secs_f64().max(f64::EPSILON);
+    info!(
+        logger,
+        "inserted {} rows and extended {} in {:.2}s ({:.0} inserted/s, {:.0} extended/s)",
+        written.inserted,
+        written.extended,
+        elapsed,
+        written.inserted as f64 / elapsed,
+        written.extended as f64 / elapsed
+    );
+
+    Ok(())
+}
+
+/// Builds the ancestor/descendant closure of the type tree described by
+/// `parents` as `(ancestor, descendant, depth)`, including a depth 0 row for
+/// each type itself. A `parent_type` cycle is followed until it comes back
+/// around.
+fn closure_rows(parents: &HashMap<i32, Option<i32>>) -> Vec<(i32, i32, i32)> {
+    let mut rows = vec![];
+    for &descendant in parents.keys() {
+        let mut seen = HashSet::new();
+        let mut ancestor = Some(descendant);
+        let mut depth = 0;
+        while let Some(ancestor_id) = ancestor
+            && seen.insert(ancestor_id)
+        {
+            rows.push((ancestor_id, descendant, depth));
+            ancestor = parents.get(&ancestor_id).copied().flatten();
+            depth += 1;
+        }
+    }
+
+    rows
+}
+
+/// Resolves each of `names` on every type in `tree` to the entry in `local` of
+/// the nearest type, walking up from the type itself through its parents, that
+/// has one. `local` is keyed by `(type path, name)`. Yields the type's
+/// `type_decl` id, the name and the entry.
+fn resolve_inherited<'a, T: Copy>(
+    tree: &'a ParsedTree,
+    names: &'a [String],
+    type_ids: &HashMap<&str, i32>,
+    local: &HashMap<(&str, &str), T>,
+) -> Vec<(i32, &'a String, T)> {
+    if names.is_empty() {
+        return vec![];
+    }
+    let parents: HashMap<&str, Option<&str>> = tree
+        .types
+        .iter()
+        .map(|type_| (type_.path.as_str(), type_.parent.as_deref()))
+        .collect();
+
+    let mut resolved = vec![];
+    for type_ in tree.types.iter() {
+        let type_decl_id = type_ids[type_.path.as_str()];
+        for name in names {
+            // Tracked in case of a `parent_type` cycle.
+            let mut seen = HashSet::new();
+            let mut current = Some(type_.path.as_str());
+            while let Some(path) = current
+                && seen.insert(path)
+            {
+                if let Some(&entry) = local.get(&(path, name.as_str())) {
+                    resolved.push((type_decl_id, name, entry));
+                    break;
+                }
+                current = parents.get(path).copied().flatten();
+            }
+        }
+    }
+
+    resolved
+}
+
+/// Resolves `location` into `source_file_id`, `line` and `column` values.
+async fn location_columns(
+    cache: &mut Cache,
+    location: Option<&ParsedLocation>,
+    txn: &DatabaseTransaction,
+) -> Result<(Option<i32>, Option<i32>, Option<i32>), IngesterError> {
+    let Some(location) = location else {
+        return Ok((None, None, None));
+    };
+    let source_file = cache.get_source_file(&location.file, txn).await?;
+
+    Ok((
+        Some(source_file.id),
+        Some(location.line as i32),
+        Some(location.column as i32),
+    ))
+}
+
+/// Inserts `rows` as multi-row inserts of at most `batch_size` rows each.
+pub(crate) async fn insert_chunked<E>(
+    txn: &DatabaseTransaction,
+    mut rows: Vec<E::ActiveModel>,
+    batch_size: usize,
+) -> Result<(), IngesterError>
+where
+    E: EntityTrait,
+    E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+{
+    while !rows.is_empty() {
+        let chunk: Vec<_> = rows.drain(..rows.len().min(batch_size)).collect();
+        E::insert_many(chunk).exec(txn).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::ParsedType;
+
+    fn parsed_type(path: &str, parent: Option<&str>) -> ParsedType {
+        ParsedType {
+            path: path.to_owned(),
+            parent: parent.map(str::to_owned),
+            explicit_parent: false,
+            location: None,
+            procs: vec![],
+            vars: vec![],
+        }
+    }
+
+    #[test]
+    fn closure_pairs_every_ancestor() {
+        let parents = HashMap::from([(1, None), (2, Some(1)), (3, Some(2)), (4, Some(1))]);
+        let mut rows = closure_rows(&parents);
+        rows.sort();
+        assert_eq!(
+            rows,
+            vec![
+                (1, 1, 0),
+                (1, 2, 1),
+                (1, 3, 2),
+                (1, 4, 1),
+                (2, 2, 0),
+                (2, 3, 1),
+                (3, 3, 0),
+                (4, 4, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn closure_stops_at_parent_type_cycles() {
+        // 2 and 3 are each other's parent; 4 hangs off the cycle.
+        let parents = HashMap::from([(2, Some(3)), (3, Some(2)), (4, Some(2))]);
+        let mut rows = closure_rows(&parents);
+        rows.sort();
+        assert_eq!(
+            rows,
+            vec![
+                (2, 2, 0),
+                (2, 3, 1),
+                (2, 4, 1),
+                (3, 2, 1),
+                (3, 3, 0),
+                (3, 4, 2),
+                (4, 4, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_to_the_nearest_ancestor() {
+        let tree = ParsedTree {
+            types: vec![
+                parsed_type("/obj", None),
+                parsed_type("/obj/item", Some("/obj")),
+                parsed_type("/obj/item/baton", Some("/obj/item")),
+                parsed_type("/obj/structure", Some("/obj")),
+            ],
+        };
+        let type_ids = HashMap::from([
+            ("/obj", 1),
+            ("/obj/item", 2),
+            ("/obj/item/baton", 3),
+            ("/obj/structure", 4),
+        ]);
+        let local = HashMap::from([(("/obj/item", "force"), 'i'), (("/obj", "name"), 'o')]);
+        let names = ["force".to_owned(), "name".to_owned(), "unset".to_owned()];
+        let mut resolved: Vec<_> = resolve_inherited(&tree, &names, &type_ids, &local)
+            .into_iter()
+            .map(|(type_decl_id, name, entry)| (type_decl_id, name.as_str(), entry))
+            .collect();
+        resolved.sort();
+        assert_eq!(
+            resolved,
+            vec![
+                (1, "name", 'o'),
+                (2, "force", 'i'),
+                (2, "name", 'o'),
+                (3, "force", 'i'),
+                (3, "name", 'o'),
+                (4, "name", 'o'),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolving_stops_at_parent_type_cycles() {
+        let tree = ParsedTree {
+            types: vec![
+                parsed_type("/datum/a", Some("/datum/b")),
+                parsed_type("/datum/b", Some("/datum/a")),
+            ],
+        };
+        let type_ids = HashMap::from([("/datum/a", 1), ("/datum/b", 2)]);
+        let local = HashMap::from([(("/datum/b", "force"), 'b')]);
+        let names = ["force".to_owned(), "unset".to_owned()];
+        let mut resolved: Vec<_> = resolve_inherited(&tree, &names, &type_ids, &local)
+            .into_iter()
+            .map(|(type_decl_id, name, entry)| (type_decl_id, name.as_str(), entry))
+            .collect();
+        resolved.sort();
+        assert_eq!(resolved, vec![(1, "force", 'b'), (2, "force", 'b')]);
+    }
+}